@@ -0,0 +1,204 @@
+//! Actions the maker and taker can take together before the timelocks allow them.
+//!
+//! An agreement executes once both parties approve it, either with one call from
+//! each or with a single call carrying the counterpart's ed25519 signature over
+//! [`Escrow::agreement_message`]. Signatures are checked against the key the
+//! counterpart registered with `register_agreement_key` and are bound to the
+//! escrow account and the current agreement nonce, so they cannot be replayed.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, AccountId, PromiseOrValue, PublicKey};
+use schemars::{gen::SchemaGenerator, schema::{Schema, SchemaObject}, JsonSchema};
+
+use shared::emit_event;
+
+use crate::{Escrow, EscrowExt, EscrowState};
+
+/// Action both parties must approve
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Agreement {
+    /// Cancel and refund before the cancellation period
+    Cancel,
+    /// Replace the withdrawal and cancellation periods with longer ones (in the escrow's timelock clock)
+    ExtendTimelocks {
+        withdrawal_period: u64,
+        cancellation_period: u64,
+    },
+}
+
+/// Agreement approved by one party and waiting for the other
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingAgreement {
+    pub agreement: Agreement,
+    pub proposer: AccountId,
+}
+
+impl JsonSchema for PendingAgreement {
+    fn schema_name() -> String {
+        "PendingAgreement".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("agreement".to_string(), gen.subschema_for::<Agreement>());
+        schema.object().properties.insert("proposer".to_string(), gen.subschema_for::<String>());
+        schema.object().required.extend(vec![
+            "agreement".to_string(),
+            "proposer".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+#[near_bindgen]
+impl Escrow {
+    /// Register the ed25519 key whose signatures count as the caller's approval (maker or taker)
+    pub fn register_agreement_key(&mut self, public_key: PublicKey) {
+        let caller = env::predecessor_account_id();
+        assert!(
+            matches!(public_key.curve_type(), near_sdk::CurveType::ED25519),
+            "Agreement key must be an ed25519 key"
+        );
+        if caller == self.roles.maker {
+            self.maker_agreement_key = Some(public_key);
+        } else if caller == self.roles.taker {
+            self.taker_agreement_key = Some(public_key);
+        } else {
+            env::panic_str("Only maker or taker can register an agreement key");
+        }
+    }
+
+    /// Cancel and refund before the cancellation period once maker and taker agree.
+    /// Returns `false` while waiting for the counterpart's approval.
+    pub fn cancel_by_agreement(&mut self, counterpart_signature: Option<String>) -> PromiseOrValue<bool> {
+        self.assert_active();
+        if !self.approve_agreement(Agreement::Cancel, counterpart_signature) {
+            return PromiseOrValue::Value(false);
+        }
+
+        self.state = EscrowState::Cancelled;
+        PromiseOrValue::Promise(self.transfer_refund())
+    }
+
+    /// Extend the withdrawal and cancellation periods once maker and taker agree.
+    /// Returns `false` while waiting for the counterpart's approval.
+    pub fn extend_timelocks(
+        &mut self,
+        withdrawal_period: u64,
+        cancellation_period: u64,
+        counterpart_signature: Option<String>,
+    ) -> bool {
+        self.assert_active();
+        let timelocks = &self.timelocks;
+        assert!(
+            withdrawal_period >= timelocks.withdrawal_period
+                && cancellation_period >= timelocks.cancellation_period,
+            "Timelocks can only be extended"
+        );
+        assert!(
+            withdrawal_period <= cancellation_period && cancellation_period <= timelocks.rescue_delay,
+            "Withdrawal period must end before cancellation, and cancellation before rescue"
+        );
+
+        let agreement = Agreement::ExtendTimelocks { withdrawal_period, cancellation_period };
+        if !self.approve_agreement(agreement, counterpart_signature) {
+            return false;
+        }
+
+        self.timelocks.withdrawal_period = withdrawal_period;
+        self.timelocks.cancellation_period = cancellation_period;
+        true
+    }
+
+    pub fn get_pending_agreement(&self) -> Option<PendingAgreement> {
+        self.pending_agreement.clone()
+    }
+
+    pub fn get_agreement_nonce(&self) -> u64 {
+        self.agreement_nonce
+    }
+
+    /// Bytes the counterpart signs to approve `agreement`:
+    /// `<escrow account>:<agreement nonce>:<agreement JSON>`
+    pub fn agreement_message(&self, agreement: Agreement) -> String {
+        format!(
+            "{}:{}:{}",
+            env::current_account_id(),
+            self.agreement_nonce,
+            near_sdk::serde_json::to_string(&agreement).unwrap()
+        )
+    }
+}
+
+impl Escrow {
+    /// Record the caller's approval of `agreement` and return whether both parties approved.
+    /// A signature from the counterpart completes the agreement in one call.
+    fn approve_agreement(&mut self, agreement: Agreement, counterpart_signature: Option<String>) -> bool {
+        let caller = env::predecessor_account_id();
+        let counterpart = if caller == self.roles.maker {
+            self.roles.taker.clone()
+        } else if caller == self.roles.taker {
+            self.roles.maker.clone()
+        } else {
+            env::panic_str("Only maker or taker can approve an agreement");
+        };
+
+        match counterpart_signature {
+            Some(signature) => self.assert_agreement_signature(&counterpart, &agreement, &signature),
+            None => {
+                let approved_by_counterpart = self
+                    .pending_agreement
+                    .as_ref()
+                    .is_some_and(|pending| pending.agreement == agreement && pending.proposer == counterpart);
+                if !approved_by_counterpart {
+                    self.pending_agreement = Some(PendingAgreement {
+                        agreement: agreement.clone(),
+                        proposer: caller.clone(),
+                    });
+                    emit_event("agreement_proposed", json!({
+                        "escrow": env::current_account_id(),
+                        "agreement": agreement,
+                        "proposer": caller,
+                    }));
+                    return false;
+                }
+            }
+        }
+
+        self.pending_agreement = None;
+        self.agreement_nonce += 1;
+        emit_event("agreement_reached", json!({
+            "escrow": env::current_account_id(),
+            "agreement": agreement,
+            "approved_by": [caller, counterpart],
+        }));
+        true
+    }
+
+    fn assert_agreement_signature(&self, signer: &AccountId, agreement: &Agreement, signature: &str) {
+        let public_key = if signer == &self.roles.maker {
+            self.maker_agreement_key.as_ref()
+        } else {
+            self.taker_agreement_key.as_ref()
+        }
+        .unwrap_or_else(|| env::panic_str("Counterpart has not registered an agreement key"));
+
+        let mut signature_bytes = [0u8; 64];
+        hex::decode_to_slice(signature.trim_start_matches("0x"), &mut signature_bytes)
+            .unwrap_or_else(|_| env::panic_str("Invalid agreement signature"));
+        // Skip the curve type prefix of the NEAR public key encoding
+        let key_bytes: [u8; 32] = public_key.as_bytes()[1..]
+            .try_into()
+            .unwrap_or_else(|_| env::panic_str("Agreement key must be an ed25519 key"));
+
+        let message = self.agreement_message(agreement.clone());
+        assert!(
+            env::ed25519_verify(&signature_bytes, message.as_bytes(), &key_bytes),
+            "Invalid agreement signature"
+        );
+    }
+}
@@ -1,25 +1,132 @@
+use std::collections::HashMap;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
 use near_sdk::{
-    env, near_bindgen, AccountId, Promise, NearToken,
-    PanicOnDefault, log, Gas,
+    env, ext_contract, near_bindgen, AccountId, Promise, PromiseOrValue, NearToken,
+    PanicOnDefault, PublicKey, log, Gas,
 };
 use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::non_fungible_token::core::{ext_nft_core, NonFungibleTokenReceiver};
+use near_contract_standards::non_fungible_token::{Token, TokenId};
+use near_contract_standards::storage_management::{ext_storage_management, StorageBalance};
+
+use shared::{emit_event, escrow_account_id, Balance, EscrowAsset, EscrowImmutables, PayoutCall, EscrowType, TimelockSchedule, Timelocks, NEAR_CHAIN_ID};
+use schemars::{gen::SchemaGenerator, schema::{Schema, SchemaObject}, JsonSchema};
 
-use shared::{EscrowImmutables, EscrowType, CryptoUtils};
-use schemars::JsonSchema;
+mod agreement;
+mod multi_token;
+mod status;
+mod wnear;
+
+pub use agreement::{Agreement, PendingAgreement};
+pub use multi_token::{ext_mt_core, MtTokenId, MultiTokenCore};
+pub use status::EscrowStatus;
+pub use wnear::{ext_wnear, WrappedNear};
 
 /// Gas for NEP141 token transfers
 const GAS_FOR_FT_TRANSFER: Gas = Gas::from_gas(20_000_000_000_000);
+/// Gas for NEP141 balance queries
+const GAS_FOR_FT_BALANCE_OF: Gas = Gas::from_gas(5_000_000_000_000);
+/// Gas for the callback that completes a rescue of the escrowed token
+const GAS_FOR_RESCUE_CALLBACK: Gas = Gas::from_gas(70_000_000_000_000);
+/// Gas for the NEP145 `storage_balance_of` lookup before NEP141 payouts
+const GAS_FOR_STORAGE_BALANCE_OF: Gas = Gas::from_gas(5_000_000_000_000);
+/// Gas for registering a payout recipient with NEP145 `storage_deposit`
+const GAS_FOR_STORAGE_DEPOSIT: Gas = Gas::from_gas(10_000_000_000_000);
+/// Gas for the callback that registers the recipient if needed and sends the tokens
+const GAS_FOR_PAYOUT_CALLBACK: Gas = Gas::from_gas(50_000_000_000_000);
+/// Gas for the callback that records a failed NEP141 payout
+const GAS_FOR_PAYOUT_RESULT: Gas = Gas::from_gas(10_000_000_000_000);
+/// NEAR attached to `storage_deposit` for unregistered recipients; with
+/// `registration_only` the token contract refunds anything above its minimum
+const STORAGE_REGISTRATION_DEPOSIT: Balance = 12_500_000_000_000_000_000_000; // 0.0125 NEAR
+/// Gas for `ft_transfer_call` payouts, most of it left to the receiver contract
+const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas::from_gas(100_000_000_000_000);
+/// Gas for resolving an `ft_transfer_call` payout and forwarding its refund
+const GAS_FOR_RESOLVE_PAYOUT_CALL: Gas = Gas::from_gas(70_000_000_000_000);
+/// Gas for NEP245 transfers
+const GAS_FOR_MT_TRANSFER: Gas = Gas::from_gas(20_000_000_000_000);
+/// Gas for NEP171 transfers
+const GAS_FOR_NFT_TRANSFER: Gas = Gas::from_gas(20_000_000_000_000);
+/// Gas for the NEP171 `nft_token` owner lookup
+const GAS_FOR_NFT_TOKEN: Gas = Gas::from_gas(5_000_000_000_000);
+/// Gas for the callback that activates a funded NEP141 escrow
+const GAS_FOR_FUNDING_CALLBACK: Gas = Gas::from_gas(10_000_000_000_000);
+/// Gas for reading the secret from the linked source escrow
+const GAS_FOR_GET_SECRET: Gas = Gas::from_gas(5_000_000_000_000);
+/// Gas for the callback that withdraws with the linked secret
+const GAS_FOR_LINKED_WITHDRAW: Gas = Gas::from_gas(80_000_000_000_000);
+/// Gas for reporting a revealed secret to the factory
+const GAS_FOR_REPORT_SECRET: Gas = Gas::from_gas(10_000_000_000_000);
+
+/// Source escrow of the same order, read by HTLC-linked destination escrows
+#[ext_contract(ext_linked_escrow)]
+pub trait LinkedEscrow {
+    fn get_secret(&self) -> Option<String>;
+}
+
+/// Factory that created this escrow, which keeps a registry of revealed secrets
+#[ext_contract(ext_escrow_factory)]
+pub trait EscrowFactory {
+    fn report_secret(&mut self, secret: String);
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(crate = "near_sdk::serde")]
 pub enum EscrowState {
+    /// Created but not yet holding the escrowed amount and safety deposit
+    Pending,
+    /// Funded and open for withdrawal or cancellation
     Active,
     Withdrawn,
     Cancelled,
-    Rescued,
+}
+
+/// Party of the swap whose rights can be handed to another account
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EscrowRole {
+    Maker,
+    Taker,
+}
+
+/// Current holders of the maker and taker roles with the maker's payout settings.
+/// They start as the signed immutables and change only through `assign_role`,
+/// so `immutables` keeps showing what was signed.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleHolders {
+    pub maker: AccountId,
+    pub taker: AccountId,
+    /// Account receiving the maker's withdrawal, defaults to the maker
+    pub receiver: Option<AccountId>,
+    /// Maker's `ft_transfer_call` for its withdrawal
+    pub payout_call: Option<PayoutCall>,
+}
+
+impl JsonSchema for RoleHolders {
+    fn schema_name() -> String {
+        "RoleHolders".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("maker".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("taker".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("receiver".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("payout_call".to_string(), gen.subschema_for::<Option<PayoutCall>>());
+        schema.object().required.extend(vec![
+            "maker".to_string(),
+            "taker".to_string(),
+            "receiver".to_string(),
+            "payout_call".to_string()
+        ]);
+        Schema::Object(schema)
+    }
 }
 
 #[near_bindgen]
@@ -27,149 +134,309 @@ pub enum EscrowState {
 pub struct Escrow {
     /// Type of escrow (Source or Destination)
     pub escrow_type: EscrowType,
-    /// Immutable escrow parameters
+    /// Immutable escrow parameters, as signed
     pub immutables: EscrowImmutables,
+    /// Current maker and taker, which `assign_role` can hand to other accounts
+    pub roles: RoleHolders,
+    /// Current timelocks, which `extend_timelocks` can extend past the signed ones
+    pub timelocks: Timelocks,
     /// Current state of the escrow
     pub state: EscrowState,
     /// Factory contract that created this escrow
     pub factory: AccountId,
     /// Secret used/revealed for withdrawal
     pub secret: Option<String>,
+    /// Agreement approved by one party and waiting for the other
+    pub pending_agreement: Option<PendingAgreement>,
+    /// Number of agreements executed, signed into agreement messages against replay
+    pub agreement_nonce: u64,
+    /// Keys whose ed25519 signatures approve agreements on behalf of maker and taker
+    pub maker_agreement_key: Option<PublicKey>,
+    pub taker_agreement_key: Option<PublicKey>,
+    /// wNEAR contract configured in the factory, used by `payout_conversion`
+    pub wnear_account: Option<AccountId>,
+    /// NEP141 payouts whose transfer failed, by recipient and token, kept for `claim_unpaid_payout`
+    pub unpaid_payouts: HashMap<(AccountId, AccountId), Balance>,
 }
 
 #[near_bindgen]
 impl Escrow {
     #[init]
-    pub fn new(escrow_type: EscrowType, immutables: EscrowImmutables) -> Self {
-        Self {
+    pub fn new(
+        escrow_type: EscrowType,
+        immutables: EscrowImmutables,
+        wnear_account: Option<AccountId>,
+    ) -> Self {
+        // Escrow accounts are created and initialized by the factory they are sub-accounts of
+        let factory = env::predecessor_account_id();
+        assert!(
+            env::current_account_id().get_parent_account_id() == Some(factory.as_ref()),
+            "Escrow can only be initialized by its parent factory account"
+        );
+        // Timelocks run from this escrow's deployment, whatever the caller passed
+        let mut immutables = immutables;
+        immutables.timelocks.stamp_deployment();
+        immutables
+            .timelocks
+            .stages()
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+        immutables
+            .validate_conversion(wnear_account.as_ref())
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+
+        let roles = RoleHolders {
+            maker: immutables.maker.clone(),
+            taker: immutables.taker.clone(),
+            receiver: immutables.receiver.clone(),
+            payout_call: immutables.payout_call.clone(),
+        };
+        let timelocks = immutables.timelocks.clone();
+        let mut escrow = Self {
             escrow_type,
             immutables,
-            state: EscrowState::Active,
-            factory: env::predecessor_account_id(),
+            roles,
+            timelocks,
+            state: EscrowState::Pending,
+            factory,
             secret: None,
+            pending_agreement: None,
+            agreement_nonce: 0,
+            maker_agreement_key: None,
+            taker_agreement_key: None,
+            wnear_account,
+            unpaid_payouts: HashMap::new(),
+        };
+        // Native NEAR arrives with the deployment, so it can be checked right away
+        if escrow.immutables.asset.is_near() && escrow.has_near_funding() {
+            escrow.state = EscrowState::Active;
+        }
+        escrow
+    }
+
+    /// Check that the escrow holds the amount and safety deposit, and activate it if so.
+    /// NEP141 and NEP245 escrows query their balance, NEP171 escrows `nft_token` on the asset contract.
+    pub fn verify_funding(&mut self) -> PromiseOrValue<bool> {
+        assert!(
+            matches!(self.state, EscrowState::Pending),
+            "Escrow is not pending"
+        );
+
+        if !self.has_near_funding() {
+            log!("Escrow is missing NEAR funding");
+            return PromiseOrValue::Value(false);
+        }
+
+        match &self.immutables.asset {
+            EscrowAsset::Near => {
+                self.state = EscrowState::Active;
+                log!("Escrow funded with {} yoctoNEAR", self.immutables.amount);
+                PromiseOrValue::Value(true)
+            }
+            EscrowAsset::Ft { contract_id } => ext_ft_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                .ft_balance_of(env::current_account_id())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_FUNDING_CALLBACK)
+                        .on_funding_balance(),
+                )
+                .into(),
+            EscrowAsset::Mt { contract_id, token_id } => ext_mt_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                .mt_balance_of(env::current_account_id(), token_id.clone())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_FUNDING_CALLBACK)
+                        .on_funding_balance(),
+                )
+                .into(),
+            EscrowAsset::Nft { contract_id, token_id } => ext_nft_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_NFT_TOKEN)
+                .nft_token(token_id.clone())
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_FUNDING_CALLBACK)
+                        .on_funding_nft(),
+                )
+                .into(),
         }
     }
 
+    /// Callback activating a NEP171 escrow once it owns the escrowed token
+    #[private]
+    pub fn on_funding_nft(&mut self, #[callback_unwrap] token: Option<Token>) -> bool {
+        let owned = token.is_some_and(|token| token.owner_id == env::current_account_id());
+        if !matches!(self.state, EscrowState::Pending) || !owned {
+            log!("Escrow does not own the escrowed NFT");
+            return false;
+        }
+
+        self.state = EscrowState::Active;
+        log!("Escrow funded with NFT");
+        true
+    }
+
+    /// Callback activating a NEP141 or NEP245 escrow once its token balance covers the amount
+    #[private]
+    pub fn on_funding_balance(&mut self, #[callback_unwrap] balance: U128) -> bool {
+        if !matches!(self.state, EscrowState::Pending) || balance.0 < self.immutables.amount {
+            log!(
+                "Escrow holds {} of {} required tokens",
+                balance.0,
+                self.immutables.amount
+            );
+            return false;
+        }
+        self.state = EscrowState::Active;
+        log!("Escrow funded with {} tokens", balance.0);
+        true
+    }
+
     /// Withdraw funds with secret
     /// Behavior depends on escrow type:
     /// - Source: maker withdraws (reveals secret for EVM claim)
     /// - Destination: taker withdraws (uses secret learned from EVM)
+    /// NEP141 maker payouts follow the maker's `payout_call` when it set one.
     pub fn withdraw(&mut self, secret: String) -> Promise {
-        // Validate state
+        let (recipient, payout_call) = match self.escrow_type {
+            EscrowType::Source => (self.maker_receiver(), self.roles.payout_call.clone()),
+            EscrowType::Destination => (self.roles.taker.clone(), None),
+        };
+        self.internal_withdraw(env::predecessor_account_id(), secret, recipient, payout_call)
+    }
+
+    /// Withdraw NEP141 funds with secret through `ft_transfer_call` to `payout_call.receiver_id`.
+    /// Only the payout recipient can choose the call; refunded tokens are forwarded to it.
+    pub fn withdraw_with_call(&mut self, secret: String, payout_call: PayoutCall) -> Promise {
         assert!(
-            matches!(self.state, EscrowState::Active),
-            "Escrow is not active"
+            matches!(self.immutables.asset, EscrowAsset::Ft { .. }),
+            "Payout calls are only supported for NEP141 escrows"
         );
+        let caller = env::predecessor_account_id();
+        let recipient = match self.escrow_type {
+            EscrowType::Source => self.maker_receiver(),
+            EscrowType::Destination => self.roles.taker.clone(),
+        };
+        assert_eq!(caller, recipient, "Only the payout recipient can set a payout call");
+        self.internal_withdraw(caller, secret, recipient, Some(payout_call))
+    }
 
-        // Validate timelock
-        assert!(
-            self.immutables.timelocks.can_withdraw(),
-            "Withdrawal period has expired"
+    /// Withdraw funds with secret to an arbitrary target (withdraw authority only)
+    pub fn withdraw_to(&mut self, secret: String, target: AccountId) -> Promise {
+        let caller = env::predecessor_account_id();
+        assert_eq!(
+            caller,
+            self.get_withdraw_authority(),
+            "Only withdraw authority can withdraw to a target"
         );
+        self.internal_withdraw(caller, secret, target, None)
+    }
 
-        // Validate secret
+    /// HTLC-linked NEAR↔NEAR mode: withdraw with the secret the source escrow of the
+    /// same order revealed, read through a cross-contract `get_secret` call
+    pub fn withdraw_with_linked_secret(&mut self) -> Promise {
         assert!(
-            CryptoUtils::verify_secret(&secret, &self.immutables.hashlock),
-            "Invalid secret"
+            matches!(self.escrow_type, EscrowType::Destination),
+            "Only destination escrows can use a linked secret"
         );
+        assert!(
+            self.immutables.src_chain_id == NEAR_CHAIN_ID && self.immutables.dst_chain_id == NEAR_CHAIN_ID,
+            "Linked secrets require a NEAR to NEAR swap"
+        );
+        self.assert_active();
 
-        // Validate caller based on escrow type
-        let caller = env::predecessor_account_id();
-        match self.escrow_type {
-            EscrowType::Source => {
-                // For source escrows, maker withdraws (no caller restriction for flexibility)
-                log!(
-                    "Source escrow withdrawal by {} with secret: {}",
-                    caller,
-                    secret
-                );
-            }
-            EscrowType::Destination => {
-                // For destination escrows, only taker can withdraw
-                assert_eq!(
-                    caller,
-                    self.immutables.taker,
-                    "Only taker can withdraw from destination escrow"
-                );
-                log!(
-                    "Destination escrow withdrawal by taker {} with secret: {}",
-                    caller,
-                    secret
-                );
-            }
-        }
-
-        // Update state
-        self.state = EscrowState::Withdrawn;
-        self.secret = Some(secret);
+        let source = escrow_account_id(
+            &self.factory,
+            &self.immutables.order_hash,
+            self.immutables.fill_index,
+            &EscrowType::Source,
+        );
+        ext_linked_escrow::ext(source)
+            .with_static_gas(GAS_FOR_GET_SECRET)
+            .get_secret()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_LINKED_WITHDRAW)
+                    .on_linked_secret(env::predecessor_account_id()),
+            )
+    }
 
-        // Transfer funds based on escrow type
-        match self.escrow_type {
-            EscrowType::Source => self.transfer_funds_to_maker(),
-            EscrowType::Destination => self.transfer_funds_to_taker(),
-        }
+    /// Callback withdrawing with the secret read from the source escrow
+    #[private]
+    pub fn on_linked_secret(
+        &mut self,
+        caller: AccountId,
+        #[callback_unwrap] secret: Option<String>,
+    ) -> Promise {
+        let secret = secret.expect("Source escrow has not revealed the secret");
+        let recipient = self.roles.taker.clone();
+        self.internal_withdraw(caller, secret, recipient, None)
     }
 
     /// Cancel escrow and refund (after cancellation period)
     /// Behavior depends on escrow type:
     /// - Source: taker can cancel (refund taker)
     /// - Destination: maker can cancel (refund maker)
+    /// An escrow that never got fully funded refunds the NEAR and tokens that did arrive.
     pub fn cancel(&mut self) -> Promise {
-        // Validate state
-        assert!(
-            matches!(self.state, EscrowState::Active),
-            "Escrow is not active"
-        );
+        self.internal_cancel(env::predecessor_account_id())
+    }
 
-        // Validate timelock
-        assert!(
-            self.immutables.timelocks.can_cancel(),
-            "Cancellation period not reached"
-        );
+    /// Withdraw on behalf of `caller`, for batches routed through the factory (factory only)
+    pub fn withdraw_for(&mut self, caller: AccountId, secret: String) -> Promise {
+        self.assert_factory();
+        let (recipient, payout_call) = match self.escrow_type {
+            EscrowType::Source => (self.maker_receiver(), self.roles.payout_call.clone()),
+            EscrowType::Destination => (self.roles.taker.clone(), None),
+        };
+        self.internal_withdraw(caller, secret, recipient, payout_call)
+    }
 
-        // Validate caller based on escrow type
+    /// Cancel on behalf of `caller`, for batches routed through the factory (factory only)
+    pub fn cancel_for(&mut self, caller: AccountId) -> Promise {
+        self.assert_factory();
+        self.internal_cancel(caller)
+    }
+
+    /// Hand the caller's role (with its withdraw/cancel authority and payouts) to `new_holder`.
+    /// Only the current holder can assign a role; a maker-set `receiver` and `payout_call` are cleared.
+    pub fn assign_role(&mut self, role: EscrowRole, new_holder: AccountId) {
+        assert!(self.is_open(), "Escrow is not active");
         let caller = env::predecessor_account_id();
-        match self.escrow_type {
-            EscrowType::Source => {
-                assert_eq!(
-                    caller,
-                    self.immutables.taker,
-                    "Only taker can cancel source escrow"
-                );
-                log!("Source escrow cancelled by taker: {}", caller);
+        match role {
+            EscrowRole::Maker => {
+                assert_eq!(caller, self.roles.maker, "Only the maker can assign the maker role");
+                self.roles.maker = new_holder.clone();
+                self.roles.receiver = None;
+                self.roles.payout_call = None;
+                self.maker_agreement_key = None;
             }
-            EscrowType::Destination => {
-                assert_eq!(
-                    caller,
-                    self.immutables.maker,
-                    "Only maker can cancel destination escrow"
-                );
-                log!("Destination escrow cancelled by maker: {}", caller);
+            EscrowRole::Taker => {
+                assert_eq!(caller, self.roles.taker, "Only the taker can assign the taker role");
+                self.roles.taker = new_holder.clone();
+                self.taker_agreement_key = None;
             }
         }
-
-        // Update state
-        self.state = EscrowState::Cancelled;
-
-        // Refund based on escrow type
-        match self.escrow_type {
-            EscrowType::Source => self.transfer_funds_to_taker(),   // Refund taker
-            EscrowType::Destination => self.transfer_funds_to_maker(), // Refund maker
+        // Approvals given by the previous holder no longer count
+        if self.pending_agreement.as_ref().is_some_and(|pending| pending.proposer == caller) {
+            self.pending_agreement = None;
         }
-    }
 
-    /// Emergency rescue funds (after rescue delay)
-    /// This is a safety mechanism for stuck funds
-    pub fn rescue_funds(&mut self, recipient: AccountId) -> Promise {
-        // Validate state
-        assert!(
-            matches!(self.state, EscrowState::Active),
-            "Escrow is not active"
-        );
+        emit_event("role_assigned", json!({
+            "escrow": env::current_account_id(),
+            "role": role,
+            "previous_holder": caller,
+            "new_holder": new_holder,
+        }));
+    }
 
+    /// Rescue stray assets (after rescue delay), mirroring EVM `rescueFunds(token, amount)`
+    /// Moves `amount` of `asset` (NEAR, NEP141, NEP171 or NEP245) to the caller; funds still
+    /// owed by an active escrow can never be rescued.
+    pub fn rescue_funds(&mut self, asset: EscrowAsset, amount: U128) -> Promise {
         // Validate timelock
         assert!(
-            self.immutables.timelocks.can_rescue(),
+            self.timelocks.can_rescue(),
             "Rescue period not reached"
         );
 
@@ -179,31 +446,226 @@ impl Escrow {
             EscrowType::Source => {
                 assert_eq!(
                     caller,
-                    self.immutables.taker,
+                    self.roles.taker,
                     "Only taker can rescue funds from source escrow"
                 );
             }
             EscrowType::Destination => {
                 assert_eq!(
                     caller,
-                    self.immutables.maker,
+                    self.roles.maker,
                     "Only maker can rescue funds from destination escrow"
                 );
             }
         }
 
-        // Update state
-        self.state = EscrowState::Rescued;
-
         log!(
-            "Funds rescued by {} to {} from {:?} escrow",
+            "Rescuing {} of {:?} to {} from {:?} escrow",
+            amount.0,
+            asset,
             caller,
-            recipient,
             self.escrow_type
         );
 
-        // Transfer to recipient
-        self.transfer_funds(recipient)
+        let owed = self.owed_asset(&asset);
+        let current = env::current_account_id();
+        match &asset {
+            EscrowAsset::Near => {
+                let available = env::account_balance()
+                    .as_yoctonear()
+                    .saturating_sub(self.storage_cost())
+                    .saturating_sub(owed);
+                assert!(
+                    amount.0 <= available,
+                    "Cannot rescue {} yoctoNEAR, only {} is not owed by the escrow",
+                    amount.0,
+                    available
+                );
+                Promise::new(caller).transfer(NearToken::from_yoctonear(amount.0))
+            }
+            EscrowAsset::Nft { .. } => {
+                assert_eq!(amount.0, 1, "NFT rescue amount must be 1");
+                assert_eq!(owed, 0, "Cannot rescue the NFT owed by the escrow");
+                self.transfer_asset(&asset, caller, amount.0)
+            }
+            // Some of the token is still owed, so check the balance before moving any of it
+            EscrowAsset::Ft { contract_id } if owed > 0 => ext_ft_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                .ft_balance_of(current.clone())
+                .then(
+                    Self::ext(current)
+                        .with_static_gas(GAS_FOR_RESCUE_CALLBACK)
+                        .on_rescue_balance(asset, amount, caller),
+                ),
+            EscrowAsset::Mt { contract_id, token_id } if owed > 0 => ext_mt_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                .mt_balance_of(current.clone(), token_id.clone())
+                .then(
+                    Self::ext(current)
+                        .with_static_gas(GAS_FOR_RESCUE_CALLBACK)
+                        .on_rescue_balance(asset, amount, caller),
+                ),
+            EscrowAsset::Ft { .. } | EscrowAsset::Mt { .. } => self.transfer_asset(&asset, caller, amount.0),
+        }
+    }
+
+    /// Callback completing a rescue of the escrowed NEP141 or NEP245 token once its balance is known
+    #[private]
+    pub fn on_rescue_balance(
+        &mut self,
+        asset: EscrowAsset,
+        amount: U128,
+        recipient: AccountId,
+        #[callback_unwrap] balance: U128,
+    ) -> Promise {
+        let available = balance.0.saturating_sub(self.owed_asset(&asset));
+        assert!(
+            amount.0 <= available,
+            "Cannot rescue {} of {:?}, only {} is not owed by the escrow",
+            amount.0,
+            asset,
+            available
+        );
+        self.transfer_asset(&asset, recipient, amount.0)
+    }
+
+    /// Callback refunding the escrowed NEP141 or NEP245 tokens that reached a cancelled pending escrow
+    #[private]
+    pub fn on_pending_refund_balance(
+        &mut self,
+        recipient: AccountId,
+        #[callback_unwrap] balance: U128,
+    ) -> PromiseOrValue<bool> {
+        let refund = balance.0.min(self.immutables.amount);
+        if refund == 0 {
+            log!("No escrowed tokens to refund");
+            return PromiseOrValue::Value(false);
+        }
+        self.transfer_amount(recipient, refund).into()
+    }
+
+    /// Callback sending NEP141 tokens once the storage registration of the account receiving
+    /// them (the payout call receiver, if any) is known. Unregistered accounts are registered
+    /// first, paid from the escrow's free NEAR (the safety deposit once the escrow is settled).
+    #[private]
+    pub fn on_recipient_storage(
+        &mut self,
+        token: AccountId,
+        recipient: AccountId,
+        amount: U128,
+        payout_call: Option<PayoutCall>,
+        #[callback_result] storage_balance: Result<Option<StorageBalance>, near_sdk::PromiseError>,
+    ) -> Promise {
+        let token_receiver = payout_call
+            .as_ref()
+            .map_or(recipient.clone(), |payout_call| payout_call.receiver_id.clone());
+        let registered = match storage_balance {
+            Ok(storage_balance) => storage_balance.is_some(),
+            Err(_) => {
+                // Tokens without NEP145 support take the plain transfer
+                log!("Could not read the storage registration of {} on {}", token_receiver, token);
+                true
+            }
+        };
+        let available = env::account_balance()
+            .as_yoctonear()
+            .saturating_sub(self.storage_cost())
+            .saturating_sub(self.owed_near());
+        let registration = if registered {
+            None
+        } else if available < STORAGE_REGISTRATION_DEPOSIT {
+            log!("Not enough NEAR to register {} on {}", token_receiver, token);
+            None
+        } else {
+            log!("Registering {} on {} before payout", token_receiver, token);
+            Some(
+                ext_storage_management::ext(token.clone())
+                    .with_static_gas(GAS_FOR_STORAGE_DEPOSIT)
+                    .with_attached_deposit(NearToken::from_yoctonear(STORAGE_REGISTRATION_DEPOSIT))
+                    .storage_deposit(Some(token_receiver), Some(true)),
+            )
+        };
+
+        let transfer = match &payout_call {
+            None => ext_ft_core::ext(token.clone())
+                .with_static_gas(GAS_FOR_FT_TRANSFER)
+                .with_attached_deposit(NearToken::from_yoctonear(1)) // Required 1 yoctoNEAR for storage
+                .ft_transfer(
+                    recipient.clone(),
+                    amount,
+                    None, // No memo
+                ),
+            Some(payout_call) => ext_ft_core::ext(token.clone())
+                .with_static_gas(GAS_FOR_FT_TRANSFER_CALL)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .ft_transfer_call(payout_call.receiver_id.clone(), amount, None, payout_call.msg.clone()),
+        };
+        let transfer = match registration {
+            Some(registration) => registration.then(transfer),
+            None => transfer,
+        };
+
+        // Failed transfers are recorded, refunds of a payout call forwarded to the recipient
+        let callback = Self::ext(env::current_account_id());
+        match payout_call {
+            None => transfer.then(
+                callback
+                    .with_static_gas(GAS_FOR_PAYOUT_RESULT)
+                    .on_token_payout(token, recipient, amount),
+            ),
+            Some(_) => transfer.then(
+                callback
+                    .with_static_gas(GAS_FOR_RESOLVE_PAYOUT_CALL)
+                    .on_payout_call_resolved(token, recipient, amount),
+            ),
+        }
+    }
+
+    /// Callback recording a NEP141 payout whose transfer failed, so the tokens stay owed to
+    /// `recipient` instead of being rescued
+    #[private]
+    pub fn on_token_payout(
+        &mut self,
+        token: AccountId,
+        recipient: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), near_sdk::PromiseError>,
+    ) -> bool {
+        if result.is_ok() {
+            return true;
+        }
+        log!("Payout of {} {} to {} failed, kept for claim_unpaid_payout", amount.0, token, recipient);
+        *self.unpaid_payouts.entry((recipient, token)).or_insert(0) += amount.0;
+        false
+    }
+
+    /// Retry a NEP141 payout of `token` to the caller whose transfer failed
+    pub fn claim_unpaid_payout(&mut self, token: AccountId) -> Promise {
+        let recipient = env::predecessor_account_id();
+        let amount = self
+            .unpaid_payouts
+            .remove(&(recipient.clone(), token.clone()))
+            .unwrap_or_else(|| env::panic_str("No unpaid payout"));
+        self.transfer_token(token, recipient, amount)
+    }
+
+    /// Callback after an `ft_transfer_call` payout: forward the unused tokens the
+    /// receiver contract returned (all of them if the call failed) to the recipient
+    #[private]
+    pub fn on_payout_call_resolved(
+        &mut self,
+        token: AccountId,
+        recipient: AccountId,
+        amount: U128,
+        #[callback_result] used_amount: Result<U128, near_sdk::PromiseError>,
+    ) -> U128 {
+        let used = used_amount.map_or(0, |used| used.0.min(amount.0));
+        let refunded = amount.0 - used;
+        if refunded > 0 {
+            log!("Forwarding {} of {} refunded by the payout call to {}", refunded, token, recipient);
+            self.transfer_token(token, recipient, refunded);
+        }
+        U128(used)
     }
 
     // === View Methods ===
@@ -212,6 +674,21 @@ impl Escrow {
         self.escrow_type.clone()
     }
 
+    /// NEP141 payout to `recipient` whose transfer failed and can be claimed
+    pub fn get_unpaid_payout(&self, recipient: AccountId, token: AccountId) -> U128 {
+        U128(self.unpaid_payouts.get(&(recipient, token)).copied().unwrap_or(0))
+    }
+
+    /// Current role holders, which differ from the immutables once a role was assigned
+    pub fn get_role_holders(&self) -> RoleHolders {
+        self.roles.clone()
+    }
+
+    /// Current timelocks, which differ from the immutables once an extension was agreed
+    pub fn get_timelocks(&self) -> Timelocks {
+        self.timelocks.clone()
+    }
+
     pub fn get_immutables(&self) -> EscrowImmutables {
         self.immutables.clone()
     }
@@ -230,155 +707,520 @@ impl Escrow {
 
     pub fn can_withdraw(&self) -> bool {
         matches!(self.state, EscrowState::Active) 
-            && self.immutables.timelocks.can_withdraw()
+            && self.timelocks.can_withdraw()
     }
 
     pub fn can_cancel(&self) -> bool {
-        matches!(self.state, EscrowState::Active) 
-            && self.immutables.timelocks.can_cancel()
+        self.is_open() && self.timelocks.can_cancel()
     }
 
     pub fn can_rescue(&self) -> bool {
-        matches!(self.state, EscrowState::Active) 
-            && self.immutables.timelocks.can_rescue()
+        self.timelocks.can_rescue()
+    }
+
+    /// Block timestamps (nanoseconds) and heights at which withdrawal ends, cancellation starts
+    /// and rescue starts; the ones not on the timelock clock are converted at `MIN_NEAR_BLOCK_TIME_MS`
+    pub fn get_timelock_stages(&self) -> TimelockSchedule {
+        self.timelocks
+            .schedule()
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()))
     }
 
     /// Get who can withdraw based on escrow type
     pub fn get_withdraw_authority(&self) -> AccountId {
         match self.escrow_type {
-            EscrowType::Source => self.immutables.maker.clone(),
-            EscrowType::Destination => self.immutables.taker.clone(),
+            EscrowType::Source => self.roles.maker.clone(),
+            EscrowType::Destination => self.roles.taker.clone(),
         }
     }
 
     /// Get who can cancel based on escrow type
     pub fn get_cancel_authority(&self) -> AccountId {
         match self.escrow_type {
-            EscrowType::Source => self.immutables.taker.clone(),
-            EscrowType::Destination => self.immutables.maker.clone(),
+            EscrowType::Source => self.roles.taker.clone(),
+            EscrowType::Destination => self.roles.maker.clone(),
         }
     }
 
     // === Private Methods ===
 
-    fn transfer_funds_to_maker(&self) -> Promise {
-        self.transfer_funds(self.immutables.maker.clone())
-    }
+    fn internal_cancel(&mut self, caller: AccountId) -> Promise {
+        // Validate state; pending escrows can be cancelled too so partial funding is not stuck
+        let pending = matches!(self.state, EscrowState::Pending);
+        if !pending {
+            self.assert_active();
+        }
 
-    fn transfer_funds_to_taker(&self) -> Promise {
-        self.transfer_funds(self.immutables.taker.clone())
-    }
+        // Validate timelock
+        assert!(
+            self.timelocks.can_cancel(),
+            "Cancellation period not reached"
+        );
 
-    fn transfer_funds(&self, recipient: AccountId) -> Promise {
-        match &self.immutables.token {
-            // Native NEAR transfer
-            None => {
-                Promise::new(recipient).transfer(NearToken::from_yoctonear(self.immutables.amount))
+        // Validate caller based on escrow type
+        match self.escrow_type {
+            EscrowType::Source => {
+                assert_eq!(
+                    caller,
+                    self.roles.taker,
+                    "Only taker can cancel source escrow"
+                );
+                log!("Source escrow cancelled by taker: {}", caller);
             }
-            // NEP141 token transfer
-            Some(token_account) => {
-                ext_ft_core::ext(token_account.clone())
-                    .with_static_gas(GAS_FOR_FT_TRANSFER)
-                    .with_attached_deposit(NearToken::from_yoctonear(1)) // Required 1 yoctoNEAR for storage
-                    .ft_transfer(
-                        recipient,
-                        U128(self.immutables.amount),
-                        None, // No memo
-                    )
+            EscrowType::Destination => {
+                assert_eq!(
+                    caller,
+                    self.roles.maker,
+                    "Only maker can cancel destination escrow"
+                );
+                log!("Destination escrow cancelled by maker: {}", caller);
             }
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, VMContext};
-    use shared::{Timelocks, CryptoUtils};
+        // Update state
+        self.state = EscrowState::Cancelled;
 
-    fn get_context(predecessor: AccountId) -> VMContext {
-        VMContextBuilder::new()
-            .predecessor_account_id(predecessor)
-            .attached_deposit(NearToken::from_near(1)) // 1 NEAR
-            .build()
+        if pending {
+            self.transfer_pending_refund(caller)
+        } else {
+            self.transfer_refund()
+        }
     }
 
-    #[test]
-    fn test_source_escrow_creation() {
-        let context = get_context(accounts(0));
-        testing_env!(context);
-
-        let secret = "test_secret_123";
-        let hashlock = CryptoUtils::create_hashlock(secret);
-        
-        let immutables = EscrowImmutables {
-            order_hash: "order_123".to_string(),
-            hashlock,
-            maker: accounts(1),
-            taker: accounts(2),
-            token: None, // Native NEAR
-            amount: 1000000000000000000000000, // 1 NEAR
-            safety_deposit: 100000000000000000000000, // 0.1 NEAR
-            timelocks: Timelocks::new(3600, 7200, 86400), // 1h, 2h, 24h
-        };
-
-        let escrow = Escrow::new(EscrowType::Source, immutables.clone());
-        
-        assert!(matches!(escrow.state, EscrowState::Active));
-        assert!(matches!(escrow.escrow_type, EscrowType::Source));
-        assert_eq!(escrow.immutables.order_hash, "order_123");
-        assert_eq!(escrow.get_withdraw_authority(), accounts(1)); // maker
-        assert_eq!(escrow.get_cancel_authority(), accounts(2));   // taker
+    fn assert_factory(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.factory,
+            "Only the factory can act on behalf of a party"
+        );
     }
 
-    #[test]
-    fn test_destination_escrow_creation() {
-        let context = get_context(accounts(0));
-        testing_env!(context);
+    fn internal_withdraw(
+        &mut self,
+        caller: AccountId,
+        secret: String,
+        recipient: AccountId,
+        payout_call: Option<PayoutCall>,
+    ) -> Promise {
+        // Validate state
+        self.assert_active();
 
-        let secret = "test_secret_123";
-        let hashlock = CryptoUtils::create_hashlock(secret);
-        
-        let immutables = EscrowImmutables {
-            order_hash: "order_123".to_string(),
-            hashlock,
-            maker: accounts(1),
-            taker: accounts(2),
-            token: None,
-            amount: 1000000000000000000000000,
-            safety_deposit: 100000000000000000000000,
-            timelocks: Timelocks::new(3600, 7200, 86400),
-        };
+        // Validate timelock
+        assert!(
+            self.timelocks.can_withdraw(),
+            "Withdrawal period has expired"
+        );
 
-        let escrow = Escrow::new(EscrowType::Destination, immutables.clone());
-        
-        assert!(matches!(escrow.state, EscrowState::Active));
-        assert!(matches!(escrow.escrow_type, EscrowType::Destination));
-        assert_eq!(escrow.get_withdraw_authority(), accounts(2)); // taker
+        // Validate secret
+        assert!(
+            self.immutables.verify_secret(&secret),
+            "Invalid secret"
+        );
+
+        // Validate caller based on escrow type
+        match self.escrow_type {
+            EscrowType::Source => {
+                // For source escrows, maker withdraws (no caller restriction for flexibility)
+                log!(
+                    "Source escrow withdrawal by {} to {} with secret: {}",
+                    caller,
+                    recipient,
+                    secret
+                );
+            }
+            EscrowType::Destination => {
+                // For destination escrows, only taker can withdraw
+                assert_eq!(
+                    caller,
+                    self.roles.taker,
+                    "Only taker can withdraw from destination escrow"
+                );
+                log!(
+                    "Destination escrow withdrawal by taker {} to {} with secret: {}",
+                    caller,
+                    recipient,
+                    secret
+                );
+            }
+        }
+
+        // Update state
+        self.state = EscrowState::Withdrawn;
+        self.secret = Some(secret.clone());
+
+        // Publish the secret through the factory so the other leg can be completed
+        // even if this escrow account is later deleted
+        let report = ext_escrow_factory::ext(self.factory.clone())
+            .with_static_gas(GAS_FOR_REPORT_SECRET)
+            .report_secret(secret);
+        let payout = match (payout_call, &self.immutables.asset) {
+            (Some(payout_call), EscrowAsset::Ft { contract_id }) => {
+                self.transfer_token_with_call(contract_id.clone(), recipient, self.immutables.amount, Some(payout_call))
+            }
+            _ => match &self.immutables.payout_conversion {
+                Some(conversion) => self.transfer_converted(recipient, conversion),
+                None => self.transfer_funds(recipient),
+            },
+        };
+        payout.and(report)
+    }
+
+    /// Account receiving the maker's withdrawal (`receiver` if the maker fixed one)
+    fn maker_receiver(&self) -> AccountId {
+        self.roles
+            .receiver
+            .clone()
+            .unwrap_or_else(|| self.roles.maker.clone())
+    }
+
+    fn transfer_funds_to_maker(&self) -> Promise {
+        self.transfer_funds(self.roles.maker.clone())
+    }
+
+    fn transfer_funds_to_taker(&self) -> Promise {
+        self.transfer_funds(self.roles.taker.clone())
+    }
+
+    /// Refund based on escrow type
+    fn transfer_refund(&self) -> Promise {
+        match self.escrow_type {
+            EscrowType::Source => self.transfer_funds_to_taker(),   // Refund taker
+            EscrowType::Destination => self.transfer_funds_to_maker(), // Refund maker
+        }
+    }
+
+    /// Refund of a cancelled escrow that was never activated: the NEAR it holds up to the
+    /// amount and safety deposit, plus whatever of the escrowed token arrived
+    fn transfer_pending_refund(&self, recipient: AccountId) -> Promise {
+        let near = env::account_balance()
+            .as_yoctonear()
+            .saturating_sub(self.storage_cost())
+            .min(self.escrowed_near());
+        let near_refund = Promise::new(recipient.clone()).transfer(NearToken::from_yoctonear(near));
+        let current = env::current_account_id();
+        match &self.immutables.asset {
+            EscrowAsset::Near => near_refund,
+            EscrowAsset::Ft { contract_id } => near_refund.and(
+                ext_ft_core::ext(contract_id.clone())
+                    .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                    .ft_balance_of(current.clone())
+                    .then(
+                        Self::ext(current)
+                            .with_static_gas(GAS_FOR_RESCUE_CALLBACK)
+                            .on_pending_refund_balance(recipient),
+                    ),
+            ),
+            EscrowAsset::Mt { contract_id, token_id } => near_refund.and(
+                ext_mt_core::ext(contract_id.clone())
+                    .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+                    .mt_balance_of(current.clone(), token_id.clone())
+                    .then(
+                        Self::ext(current)
+                            .with_static_gas(GAS_FOR_RESCUE_CALLBACK)
+                            .on_pending_refund_balance(recipient),
+                    ),
+            ),
+            // Fails without effect if the escrow never received the token
+            EscrowAsset::Nft { .. } => near_refund.and(self.transfer_funds(recipient)),
+        }
+    }
+
+    fn transfer_funds(&self, recipient: AccountId) -> Promise {
+        self.transfer_amount(recipient, self.immutables.amount)
+    }
+
+    /// Payout of `amount` of the escrowed asset (NEP171 tokens are moved whole)
+    fn transfer_amount(&self, recipient: AccountId, amount: Balance) -> Promise {
+        self.transfer_asset(&self.immutables.asset, recipient, amount)
+    }
+
+    /// Transfer of `amount` of `asset` from the escrow account
+    fn transfer_asset(&self, asset: &EscrowAsset, recipient: AccountId, amount: Balance) -> Promise {
+        match asset {
+            // Native NEAR transfer
+            EscrowAsset::Near => {
+                Promise::new(recipient).transfer(NearToken::from_yoctonear(amount))
+            }
+            // NEP141 token transfer
+            EscrowAsset::Ft { contract_id } => {
+                self.transfer_token(contract_id.clone(), recipient, amount)
+            }
+            // NEP171 token transfer
+            EscrowAsset::Nft { contract_id, token_id } => ext_nft_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_NFT_TRANSFER)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .nft_transfer(recipient, token_id.clone(), None, None),
+            // NEP245 token transfer
+            EscrowAsset::Mt { contract_id, token_id } => ext_mt_core::ext(contract_id.clone())
+                .with_static_gas(GAS_FOR_MT_TRANSFER)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .mt_transfer(recipient, token_id.clone(), U128(amount), None, None),
+        }
+    }
+
+    /// NEP141 payout, registering the recipient's storage on the token first if needed
+    fn transfer_token(&self, token: AccountId, recipient: AccountId, amount: Balance) -> Promise {
+        self.transfer_token_with_call(token, recipient, amount, None)
+    }
+
+    /// NEP141 payout to `recipient`, through `ft_transfer_call` when a payout call is given
+    fn transfer_token_with_call(
+        &self,
+        token: AccountId,
+        recipient: AccountId,
+        amount: Balance,
+        payout_call: Option<PayoutCall>,
+    ) -> Promise {
+        let (token_receiver, callback_gas) = match &payout_call {
+            None => (recipient.clone(), GAS_FOR_PAYOUT_CALLBACK),
+            Some(payout_call) => (
+                payout_call.receiver_id.clone(),
+                GAS_FOR_PAYOUT_CALLBACK
+                    .saturating_add(GAS_FOR_FT_TRANSFER_CALL)
+                    .saturating_add(GAS_FOR_RESOLVE_PAYOUT_CALL),
+            ),
+        };
+        ext_storage_management::ext(token.clone())
+            .with_static_gas(GAS_FOR_STORAGE_BALANCE_OF)
+            .storage_balance_of(token_receiver)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(callback_gas)
+                    .on_recipient_storage(token, recipient, U128(amount), payout_call),
+            )
+    }
+
+    fn assert_active(&self) {
+        assert!(
+            !matches!(self.state, EscrowState::Pending),
+            "Escrow is not funded"
+        );
+        assert!(
+            matches!(self.state, EscrowState::Active),
+            "Escrow is not active"
+        );
+    }
+
+    /// Whether the escrow still owes its amount and safety deposit to the parties
+    fn is_open(&self) -> bool {
+        matches!(self.state, EscrowState::Pending | EscrowState::Active)
+    }
+
+    /// NEAR still owed to the parties by an active escrow. A pending escrow owes nothing:
+    /// whatever funding arrived is returned by cancelling it.
+    fn owed_near(&self) -> Balance {
+        if matches!(self.state, EscrowState::Active) {
+            self.escrowed_near()
+        } else {
+            0
+        }
+    }
+
+    /// Amount of `asset` the escrow still owes: the escrowed amount while active, plus
+    /// failed NEP141 payouts
+    fn owed_asset(&self, asset: &EscrowAsset) -> Balance {
+        match asset {
+            EscrowAsset::Near => self.owed_near(),
+            EscrowAsset::Ft { contract_id } => self.owed_tokens(contract_id),
+            EscrowAsset::Nft { .. } | EscrowAsset::Mt { .. } => {
+                if *asset == self.immutables.asset && matches!(self.state, EscrowState::Active) {
+                    self.immutables.amount
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// Amount of NEP141 `token` the escrow still owes: the escrowed amount while active,
+    /// plus payouts whose transfer failed
+    fn owed_tokens(&self, token: &AccountId) -> Balance {
+        let escrowed = match &self.immutables.asset {
+            EscrowAsset::Ft { contract_id } if contract_id == token && matches!(self.state, EscrowState::Active) => {
+                self.immutables.amount
+            }
+            _ => 0,
+        };
+        let unpaid: Balance = self
+            .unpaid_payouts
+            .iter()
+            .filter(|((_, unpaid_token), _)| unpaid_token == token)
+            .map(|(_, amount)| amount)
+            .sum();
+        escrowed + unpaid
+    }
+
+    /// NEAR an escrow holds once funded: the safety deposit, plus the amount for native NEAR escrows
+    fn escrowed_near(&self) -> Balance {
+        match self.immutables.asset {
+            EscrowAsset::Near => self.immutables.amount + self.immutables.safety_deposit,
+            EscrowAsset::Ft { .. } | EscrowAsset::Nft { .. } | EscrowAsset::Mt { .. } => {
+                self.immutables.safety_deposit
+            }
+        }
+    }
+
+    fn has_near_funding(&self) -> bool {
+        let available = env::account_balance()
+            .as_yoctonear()
+            .saturating_sub(self.storage_cost());
+        available >= self.escrowed_near()
+    }
+
+    fn storage_cost(&self) -> Balance {
+        env::storage_byte_cost().as_yoctonear() * u128::from(env::storage_usage())
+    }
+}
+
+/// Accept NEP141 tokens sent to the escrow so that stray deposits can be rescued
+#[near_bindgen]
+impl FungibleTokenReceiver for Escrow {
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        log!(
+            "Received {} of {} from {} with msg: {}",
+            amount.0,
+            env::predecessor_account_id(),
+            sender_id,
+            msg
+        );
+        PromiseOrValue::Value(U128(0))
+    }
+}
+
+/// Accept the escrowed NEP171 token and activate the escrow once its safety deposit is there too.
+/// Any other token is returned to its sender.
+#[near_bindgen]
+impl NonFungibleTokenReceiver for Escrow {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let expected = EscrowAsset::Nft {
+            contract_id: env::predecessor_account_id(),
+            token_id: token_id.clone(),
+        };
+        if self.immutables.asset != expected || !matches!(self.state, EscrowState::Pending) {
+            log!("Returning NFT {} of {} to {}", token_id, env::predecessor_account_id(), previous_owner_id);
+            return PromiseOrValue::Value(true);
+        }
+
+        log!("Received NFT {} from {} with msg: {}", token_id, sender_id, msg);
+        if self.has_near_funding() {
+            self.state = EscrowState::Active;
+            log!("Escrow funded with NFT");
+        }
+        PromiseOrValue::Value(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, VMContext};
+    use shared::{BitcoinHashlock, Timelocks, TimelockClock, TimelockStage, CryptoUtils, NearConversion, NEAR_CHAIN_ID};
+
+    /// Context of a call from `predecessor` to an escrow deployed under it
+    fn get_context(predecessor: AccountId) -> VMContext {
+        VMContextBuilder::new()
+            .current_account_id(format!("escrow.{}", predecessor).parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(NearToken::from_near(1)) // 1 NEAR
+            .build()
+    }
+
+    fn test_immutables(hashlock: String) -> EscrowImmutables {
+        EscrowImmutables {
+            order_hash: "order_123".to_string(),
+            hashlock,
+            maker: accounts(1),
+            taker: accounts(2),
+            asset: EscrowAsset::Near,
+            amount: 1000000000000000000000000, // 1 NEAR
+            safety_deposit: 100000000000000000000000, // 0.1 NEAR
+            timelocks: Timelocks::new(3600, 7200, 86400), // 1h, 2h, 24h
+            nonce: 0,
+            src_chain_id: 1,
+            dst_chain_id: NEAR_CHAIN_ID,
+            counterpart_token: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            counterpart_amount: "400000000000000000".to_string(),
+            evm_maker: Some("0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string()),
+            evm_receiver: None,
+            deadline: 3600,
+            receiver: None,
+            fill_index: 0,
+            allow_partial_fills: false,
+            payout_call: None,
+            payout_conversion: None,
+            bitcoin_hashlock: None,
+            bitcoin_timelock_preset: None,
+        }
+    }
+
+    #[test]
+    fn test_source_escrow_creation() {
+        let context = get_context(accounts(0));
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let immutables = test_immutables(hashlock);
+
+        let escrow = Escrow::new(EscrowType::Source, immutables.clone(), None);
+        
+        assert!(matches!(escrow.state, EscrowState::Active));
+        assert!(matches!(escrow.escrow_type, EscrowType::Source));
+        assert_eq!(escrow.immutables.order_hash, "order_123");
+        assert_eq!(escrow.get_immutables().dst_chain_id, NEAR_CHAIN_ID);
+        assert_eq!(escrow.get_withdraw_authority(), accounts(1)); // maker
+        assert_eq!(escrow.get_cancel_authority(), accounts(2));   // taker
+    }
+
+    #[test]
+    #[should_panic(expected = "Escrow can only be initialized by its parent factory account")]
+    fn test_only_parent_factory_initializes() {
+        let mut context = get_context(accounts(3));
+        context.current_account_id = "escrow.factory.near".parse().unwrap();
+        testing_env!(context);
+
+        Escrow::new(EscrowType::Source, test_immutables(CryptoUtils::create_hashlock("test_secret_123")), None);
+    }
+
+    #[test]
+    fn test_destination_escrow_creation() {
+        let context = get_context(accounts(0));
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let immutables = test_immutables(hashlock);
+
+        let escrow = Escrow::new(EscrowType::Destination, immutables.clone(), None);
+        
+        assert!(matches!(escrow.state, EscrowState::Active));
+        assert!(matches!(escrow.escrow_type, EscrowType::Destination));
+        assert_eq!(escrow.get_withdraw_authority(), accounts(2)); // taker
         assert_eq!(escrow.get_cancel_authority(), accounts(1));   // maker
     }
 
     #[test]
     fn test_source_escrow_withdrawal() {
-        let mut context = get_context(accounts(1)); // maker
+        let context = get_context(accounts(1)); // maker
         testing_env!(context);
 
         let secret = "test_secret_123";
         let hashlock = CryptoUtils::create_hashlock(secret);
-        
-        let immutables = EscrowImmutables {
-            order_hash: "order_123".to_string(),
-            hashlock,
-            maker: accounts(1),
-            taker: accounts(2),
-            token: None,
-            amount: 1000000000000000000000000,
-            safety_deposit: 100000000000000000000000,
-            timelocks: Timelocks::new(3600, 7200, 86400),
-        };
+        let immutables = test_immutables(hashlock);
 
-        let mut escrow = Escrow::new(EscrowType::Source, immutables);
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
         
         // Maker should be able to withdraw with correct secret
         let _promise = escrow.withdraw(secret.to_string());
@@ -387,26 +1229,35 @@ mod tests {
         assert_eq!(escrow.secret, Some(secret.to_string()));
     }
 
+    #[test]
+    fn test_withdraw_reports_secret_to_factory() {
+        testing_env!(get_context(accounts(0))); // factory
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut escrow = Escrow::new(EscrowType::Source, test_immutables(hashlock), None);
+
+        testing_env!(get_context(accounts(1)));
+        let _ = escrow.withdraw(secret.to_string());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        let report = receipts.iter().find(|receipt| receipt.receiver_id == accounts(0)).unwrap();
+        assert!(report.actions.iter().any(|action| matches!(
+            action,
+            near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. }
+                if method_name == b"report_secret"
+        )));
+    }
+
     #[test]
     fn test_destination_escrow_withdrawal() {
-        let mut context = get_context(accounts(2)); // taker
+        let context = get_context(accounts(2)); // taker
         testing_env!(context);
 
         let secret = "test_secret_123";
         let hashlock = CryptoUtils::create_hashlock(secret);
-        
-        let immutables = EscrowImmutables {
-            order_hash: "order_123".to_string(),
-            hashlock,
-            maker: accounts(1),
-            taker: accounts(2),
-            token: None,
-            amount: 1000000000000000000000000,
-            safety_deposit: 100000000000000000000000,
-            timelocks: Timelocks::new(3600, 7200, 86400),
-        };
+        let immutables = test_immutables(hashlock);
 
-        let mut escrow = Escrow::new(EscrowType::Destination, immutables);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
         
         // Taker should be able to withdraw with correct secret
         let _promise = escrow.withdraw(secret.to_string());
@@ -418,26 +1269,865 @@ mod tests {
     #[test]
     #[should_panic(expected = "Only taker can withdraw from destination escrow")]
     fn test_destination_escrow_maker_cannot_withdraw() {
-        let mut context = get_context(accounts(1)); // maker
+        let context = get_context(accounts(1)); // maker
         testing_env!(context);
 
         let secret = "test_secret_123";
         let hashlock = CryptoUtils::create_hashlock(secret);
-        
-        let immutables = EscrowImmutables {
-            order_hash: "order_123".to_string(),
-            hashlock,
-            maker: accounts(1),
-            taker: accounts(2),
-            token: None,
-            amount: 1000000000000000000000000,
-            safety_deposit: 100000000000000000000000,
-            timelocks: Timelocks::new(3600, 7200, 86400),
-        };
+        let immutables = test_immutables(hashlock);
 
-        let mut escrow = Escrow::new(EscrowType::Destination, immutables);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
         
         // Maker should not be able to withdraw from destination escrow
         escrow.withdraw(secret.to_string());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_withdraw_to_target() {
+        let context = get_context(accounts(2)); // taker
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let immutables = test_immutables(hashlock);
+
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        let _ = escrow.withdraw_to(secret.to_string(), accounts(4));
+
+        assert!(matches!(escrow.state, EscrowState::Withdrawn));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(4));
+    }
+
+    #[test]
+    fn test_deployed_at_is_stamped_on_chain() {
+        let mut context = get_context(accounts(0));
+        context.block_timestamp = 5_000_000_000;
+        testing_env!(context);
+
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock("test_secret_123"));
+        immutables.timelocks.deployed_at = 1; // backdated by the caller
+        let escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        assert_eq!(escrow.immutables.timelocks.deployed_at, 5_000_000_000);
+        let stages = escrow.get_timelock_stages();
+        assert_eq!(
+            stages.timestamps.withdrawal_end,
+            5_000_000_000 + escrow.immutables.timelocks.withdrawal_period * 1_000_000_000
+        );
+    }
+
+    #[test]
+    fn test_status_reports_stage_and_actors() {
+        testing_env!(get_context(accounts(0)));
+        let escrow = Escrow::new(
+            EscrowType::Destination,
+            test_immutables(CryptoUtils::create_hashlock("test_secret_123")),
+            None,
+        );
+
+        let status = escrow.get_status();
+        assert_eq!(status.stage, TimelockStage::Withdrawal);
+        assert_eq!(status.seconds_to_next_stage, Some(escrow.immutables.timelocks.withdrawal_period));
+        // At most this many 600ms blocks
+        assert_eq!(
+            status.blocks_to_next_stage,
+            Some((escrow.immutables.timelocks.withdrawal_period * 1000).div_ceil(600))
+        );
+        assert_eq!(status.withdrawer, Some(accounts(2)));
+        assert_eq!(status.canceller, None);
+
+        let mut context = get_context(accounts(0));
+        context.block_timestamp = status.stages.timestamps.cancellation_start;
+        testing_env!(context);
+        let status = escrow.get_status();
+        assert_eq!(status.stage, TimelockStage::Cancellation);
+        assert_eq!(status.withdrawer, None);
+        assert_eq!(status.canceller, Some(accounts(1)));
+        assert_eq!(status.rescuer, None);
+    }
+
+    #[test]
+    fn test_block_height_clock_status() {
+        let mut context = get_context(accounts(0));
+        context.block_index = 1_000;
+        testing_env!(context.clone());
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock("test_secret_123"));
+        immutables.timelocks.clock = TimelockClock::BlockHeight;
+        immutables.timelocks.deployed_at_height = 1; // backdated by the caller
+        let escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        context.block_index = 1_010;
+        testing_env!(context);
+        let status = escrow.get_status();
+        assert_eq!(status.deployed_at_height, 1_000);
+        assert_eq!(status.block_height, 1_010);
+        assert_eq!(status.stages.block_heights.withdrawal_end, 1_000 + escrow.immutables.timelocks.withdrawal_period);
+        assert_eq!(status.blocks_to_next_stage, Some(escrow.immutables.timelocks.withdrawal_period - 10));
+        // At least 600ms per remaining block
+        assert_eq!(
+            status.seconds_to_next_stage,
+            Some(((escrow.immutables.timelocks.withdrawal_period - 10) * 600).div_ceil(1000))
+        );
+    }
+
+    #[test]
+    fn test_bitcoin_hash160_withdrawal() {
+        testing_env!(get_context(accounts(2)));
+        let preimage = "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+        let mut immutables = test_immutables("c00f4e3c177f4f4c4aa0cf3d72dc675eabeb74a3".to_string());
+        immutables.bitcoin_hashlock = Some(BitcoinHashlock::Hash160);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        let _ = escrow.withdraw(preimage.to_string());
+        assert!(matches!(escrow.state, EscrowState::Withdrawn));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid secret")]
+    fn test_bitcoin_hashlock_rejects_hex_string_secret() {
+        testing_env!(get_context(accounts(2)));
+        let preimage = "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+        // Hashing the hex string instead of the raw bytes does not match
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(preimage));
+        immutables.bitcoin_hashlock = Some(BitcoinHashlock::Sha256);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        escrow.withdraw(preimage.to_string());
+    }
+
+    #[test]
+    fn test_factory_withdraws_for_taker() {
+        testing_env!(get_context(accounts(0))); // factory
+        let secret = "test_secret_123";
+        let mut escrow = Escrow::new(
+            EscrowType::Destination,
+            test_immutables(CryptoUtils::create_hashlock(secret)),
+            None,
+        );
+
+        let _ = escrow.withdraw_for(accounts(2), secret.to_string());
+
+        assert!(matches!(escrow.state, EscrowState::Withdrawn));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the factory can act on behalf of a party")]
+    fn test_withdraw_for_requires_factory() {
+        testing_env!(get_context(accounts(0))); // factory
+        let secret = "test_secret_123";
+        let mut escrow = Escrow::new(
+            EscrowType::Destination,
+            test_immutables(CryptoUtils::create_hashlock(secret)),
+            None,
+        );
+
+        testing_env!(get_context(accounts(3)));
+        escrow.withdraw_for(accounts(2), secret.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only withdraw authority can withdraw to a target")]
+    fn test_withdraw_to_requires_authority() {
+        let context = get_context(accounts(3)); // outsider
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let immutables = test_immutables(hashlock);
+
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+        escrow.withdraw_to(secret.to_string(), accounts(3));
+    }
+
+    #[test]
+    fn test_source_withdrawal_pays_receiver() {
+        let context = get_context(accounts(3)); // anyone may trigger a source withdrawal
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut immutables = test_immutables(hashlock);
+        immutables.receiver = Some(accounts(5));
+
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+        let _ = escrow.withdraw(secret.to_string());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(5));
+    }
+
+    fn get_rescue_context(predecessor: AccountId, balance: NearToken) -> VMContext {
+        VMContextBuilder::new()
+            .current_account_id(format!("escrow.{}", predecessor).parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .account_balance(balance)
+            .storage_usage(1_000) // 0.01 NEAR locked for storage
+            .block_timestamp(86401 * 1_000_000_000) // past the 24h rescue delay
+            .build()
+    }
+
+    #[test]
+    fn test_rescue_stray_near() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Source, test_immutables(hashlock), None);
+
+        // 1.1 NEAR is owed, 0.5 NEAR was sent by mistake
+        testing_env!(get_rescue_context(accounts(2), NearToken::from_millinear(1600)));
+        let _ = escrow.rescue_funds(EscrowAsset::Near, U128(NearToken::from_millinear(400).as_yoctonear()));
+
+        assert!(matches!(escrow.state, EscrowState::Active));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.last().unwrap().receiver_id, accounts(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not owed by the escrow")]
+    fn test_rescue_cannot_take_owed_funds() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Source, test_immutables(hashlock), None);
+
+        testing_env!(get_rescue_context(accounts(2), NearToken::from_millinear(1600)));
+        escrow.rescue_funds(EscrowAsset::Near, U128(NearToken::from_near(1).as_yoctonear()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rescue period not reached")]
+    fn test_rescue_before_delay() {
+        testing_env!(get_context(accounts(2)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Source, test_immutables(hashlock), None);
+
+        escrow.rescue_funds(EscrowAsset::Ft { contract_id: accounts(4) }, U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Escrow is not funded")]
+    fn test_underfunded_escrow_stays_pending() {
+        let mut context = get_context(accounts(2));
+        context.account_balance = NearToken::from_millinear(500);
+        context.attached_deposit = NearToken::from_near(0);
+        context.storage_usage = 1_000;
+        testing_env!(context);
+
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        assert!(matches!(escrow.state, EscrowState::Pending));
+        assert!(matches!(escrow.verify_funding(), PromiseOrValue::Value(false)));
+        escrow.withdraw(secret.to_string());
+    }
+
+    #[test]
+    fn test_pending_escrow_cancel_refunds_partial_funding() {
+        let mut context = get_context(accounts(0));
+        context.account_balance = NearToken::from_millinear(500);
+        context.attached_deposit = NearToken::from_near(0);
+        context.storage_usage = 1_000; // 0.01 NEAR locked for storage
+        testing_env!(context.clone());
+
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+        assert!(matches!(escrow.state, EscrowState::Pending));
+        assert_eq!(escrow.owed_near(), 0);
+
+        context.predecessor_account_id = accounts(1); // maker
+        context.block_timestamp = 7201 * 1_000_000_000; // past the cancellation timelock
+        testing_env!(context);
+        assert!(escrow.can_cancel());
+        let _ = escrow.cancel();
+
+        assert!(matches!(escrow.state, EscrowState::Cancelled));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(1));
+        assert!(matches!(
+            &receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if *deposit == NearToken::from_millinear(490)
+        ));
+    }
+
+    #[test]
+    fn test_pending_token_escrow_cancel_refunds_tokens() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.clone());
+
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut immutables = test_immutables(hashlock);
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+        assert!(matches!(escrow.state, EscrowState::Pending));
+
+        context.predecessor_account_id = accounts(2); // taker
+        context.block_timestamp = 7201 * 1_000_000_000;
+        testing_env!(context);
+        let _ = escrow.cancel();
+        assert!(matches!(escrow.state, EscrowState::Cancelled));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == accounts(4)));
+
+        // Only part of the amount arrived before the cancellation
+        assert!(matches!(escrow.on_pending_refund_balance(accounts(2), U128(200)), PromiseOrValue::Promise(_)));
+        assert!(matches!(escrow.on_pending_refund_balance(accounts(2), U128(0)), PromiseOrValue::Value(false)));
+    }
+
+    #[test]
+    fn test_token_escrow_funding() {
+        testing_env!(get_context(accounts(0)));
+
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut immutables = test_immutables(hashlock);
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        assert!(matches!(escrow.state, EscrowState::Pending));
+
+        assert!(!escrow.on_funding_balance(U128(499)));
+        assert!(matches!(escrow.state, EscrowState::Pending));
+
+        assert!(escrow.on_funding_balance(U128(500)));
+        assert!(matches!(escrow.state, EscrowState::Active));
+    }
+
+    fn near_to_near_immutables(hashlock: String) -> EscrowImmutables {
+        let mut immutables = test_immutables(hashlock);
+        immutables.order_hash = "0xabcdef0123".to_string();
+        immutables.src_chain_id = NEAR_CHAIN_ID;
+        immutables.counterpart_token = "near".to_string();
+        immutables
+    }
+
+    #[test]
+    fn test_withdraw_with_linked_secret() {
+        testing_env!(get_context(accounts(0))); // factory
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut escrow = Escrow::new(EscrowType::Destination, near_to_near_immutables(hashlock), None);
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw_with_linked_secret();
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(
+            receipts[0].receiver_id,
+            escrow_account_id(&accounts(0), "0xabcdef0123", 0, &EscrowType::Source)
+        );
+
+        // Callback with the secret revealed on the source escrow
+        let _ = escrow.on_linked_secret(accounts(2), Some(secret.to_string()));
+        assert!(matches!(escrow.state, EscrowState::Withdrawn));
+        assert_eq!(escrow.secret, Some(secret.to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Linked secrets require a NEAR to NEAR swap")]
+    fn test_linked_secret_requires_near_to_near() {
+        testing_env!(get_context(accounts(2)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        escrow.withdraw_with_linked_secret();
+    }
+
+    /// Maker's agreement key and its signature over `escrow.near:0:"Cancel"`
+    const AGREEMENT_KEY: &str = "ed25519:9C6hybhQ6Aycep9jaUnP6uL9ZYvDjUp1aSkFWPUFJtpj";
+    const CANCEL_SIGNATURE: &str = "90d4239e40ef4391d5a37f8530646f35b7aa4e44ca09a86b3074046b6a9c4056b579da762ac53502225e7a9a9fc753e72cb77901a5e14bc1c9e4a6a1b141f900";
+
+    fn get_escrow_context(predecessor: AccountId) -> VMContext {
+        VMContextBuilder::new()
+            .current_account_id("escrow.near".parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(NearToken::from_near(1))
+            .build()
+    }
+
+    #[test]
+    fn test_cancel_by_agreement() {
+        testing_env!(get_context(accounts(1))); // maker
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        assert!(matches!(escrow.cancel_by_agreement(None), PromiseOrValue::Value(false)));
+        assert!(matches!(escrow.state, EscrowState::Active));
+        assert_eq!(escrow.get_pending_agreement().unwrap().agreement, Agreement::Cancel);
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.cancel_by_agreement(None);
+        assert!(matches!(escrow.state, EscrowState::Cancelled));
+        assert_eq!(escrow.get_agreement_nonce(), 1);
+        assert!(escrow.get_pending_agreement().is_none());
+    }
+
+    #[test]
+    fn test_cancel_by_agreement_with_signature() {
+        testing_env!(get_escrow_context("near".parse().unwrap())); // factory
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+        testing_env!(get_escrow_context(accounts(1))); // maker
+        escrow.register_agreement_key(AGREEMENT_KEY.parse().unwrap());
+
+        testing_env!(get_escrow_context(accounts(2))); // taker, carrying the maker's approval
+        let _ = escrow.cancel_by_agreement(Some(CANCEL_SIGNATURE.to_string()));
+        assert!(matches!(escrow.state, EscrowState::Cancelled));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid agreement signature")]
+    fn test_agreement_signature_is_bound_to_agreement() {
+        testing_env!(get_escrow_context("near".parse().unwrap())); // factory
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+        testing_env!(get_escrow_context(accounts(1))); // maker
+        escrow.register_agreement_key(AGREEMENT_KEY.parse().unwrap());
+
+        // The cancel signature does not approve a timelock extension
+        testing_env!(get_escrow_context(accounts(2)));
+        escrow.extend_timelocks(7200, 10800, Some(CANCEL_SIGNATURE.to_string()));
+    }
+
+    #[test]
+    fn test_extend_timelocks_by_agreement() {
+        testing_env!(get_context(accounts(2))); // taker
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Source, test_immutables(hashlock), None);
+
+        assert!(!escrow.extend_timelocks(7200, 10800, None));
+        assert_eq!(escrow.get_timelocks().withdrawal_period, 3600);
+
+        testing_env!(get_context(accounts(1))); // maker
+        assert!(escrow.extend_timelocks(7200, 10800, None));
+        assert_eq!(escrow.get_timelocks().withdrawal_period, 7200);
+        assert_eq!(escrow.get_timelocks().cancellation_period, 10800);
+        assert_eq!(escrow.get_timelock_stages().timestamps.withdrawal_end, escrow.get_timelocks().deployed_at + 7200 * 1_000_000_000);
+        // The signed immutables are unchanged
+        assert_eq!(escrow.get_immutables().timelocks.withdrawal_period, 3600);
+        assert_eq!(escrow.get_immutables().timelocks.cancellation_period, 7200);
+    }
+
+    #[test]
+    fn test_assign_taker_role() {
+        testing_env!(get_context(accounts(2))); // taker
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        escrow.assign_role(EscrowRole::Taker, accounts(4));
+        assert_eq!(escrow.get_withdraw_authority(), accounts(4));
+
+        testing_env!(get_context(accounts(4))); // new taker
+        let _ = escrow.withdraw(secret.to_string());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(4));
+    }
+
+    #[test]
+    fn test_assign_maker_role_clears_receiver() {
+        testing_env!(get_context(accounts(1))); // maker
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut immutables = test_immutables(hashlock);
+        immutables.receiver = Some(accounts(5));
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        escrow.assign_role(EscrowRole::Maker, accounts(4));
+        assert_eq!(escrow.get_cancel_authority(), accounts(4));
+        assert_eq!(escrow.get_role_holders().receiver, None);
+        // The signed immutables are unchanged
+        assert_eq!(escrow.get_immutables().maker, accounts(1));
+        assert_eq!(escrow.get_immutables().receiver, Some(accounts(5)));
+    }
+
+    #[test]
+    fn test_withdraw_pays_reassigned_maker() {
+        testing_env!(get_context(accounts(1))); // maker
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.receiver = Some(accounts(5));
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+        escrow.assign_role(EscrowRole::Maker, accounts(4));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(4));
+        assert!(!receipts.iter().any(|receipt| receipt.receiver_id == accounts(5)));
+    }
+
+    #[test]
+    fn test_assign_maker_role_clears_payout_call() {
+        testing_env!(get_context(accounts(1))); // maker
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+        immutables.payout_call = Some(PayoutCall { receiver_id: "dex.near".parse().unwrap(), msg: String::new() });
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+        escrow.on_funding_balance(U128(500));
+
+        escrow.assign_role(EscrowRole::Maker, accounts(5));
+        assert!(escrow.get_role_holders().payout_call.is_none());
+
+        // The new maker is paid with a plain transfer
+        let _ = escrow.withdraw(secret.to_string());
+        let args = near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .find_map(|action| match action {
+                near_sdk::mock::MockAction::FunctionCallWeight { method_name, args, .. }
+                    if method_name == b"on_recipient_storage" => Some(args),
+                _ => None,
+            })
+            .unwrap();
+        let args: near_sdk::serde_json::Value = near_sdk::serde_json::from_slice(&args).unwrap();
+        assert_eq!(args["recipient"], accounts(5).to_string());
+        assert!(args["payout_call"].is_null());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the taker can assign the taker role")]
+    fn test_only_holder_assigns_role() {
+        testing_env!(get_context(accounts(1))); // maker
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        escrow.assign_role(EscrowRole::Taker, accounts(1));
+    }
+
+    fn nft_immutables(hashlock: String) -> EscrowImmutables {
+        let mut immutables = test_immutables(hashlock);
+        immutables.asset = EscrowAsset::Nft {
+            contract_id: "nft.near".parse().unwrap(),
+            token_id: "42".to_string(),
+        };
+        immutables.amount = 1;
+        immutables
+    }
+
+    #[test]
+    fn test_nft_escrow_funding_and_withdrawal() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let hashlock = CryptoUtils::create_hashlock(secret);
+        let mut escrow = Escrow::new(EscrowType::Destination, nft_immutables(hashlock), None);
+        assert!(matches!(escrow.state, EscrowState::Pending));
+
+        // Some other token is sent back
+        testing_env!(get_context("nft.near".parse().unwrap()));
+        let returned = escrow.nft_on_transfer(accounts(1), accounts(1), "7".to_string(), String::new());
+        assert!(matches!(returned, PromiseOrValue::Value(true)));
+        assert!(matches!(escrow.state, EscrowState::Pending));
+
+        let kept = escrow.nft_on_transfer(accounts(1), accounts(1), "42".to_string(), String::new());
+        assert!(matches!(kept, PromiseOrValue::Value(false)));
+        assert!(matches!(escrow.state, EscrowState::Active));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, "nft.near".parse::<AccountId>().unwrap());
+        assert!(receipts[0].actions.iter().any(|action| matches!(
+            action,
+            near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. }
+                if method_name == b"nft_transfer"
+        )));
+    }
+
+    #[test]
+    fn test_nft_escrow_funding_by_owner_lookup() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, nft_immutables(hashlock), None);
+
+        let token = |owner_id: AccountId| Token {
+            token_id: "42".to_string(),
+            owner_id,
+            metadata: None,
+            approved_account_ids: None,
+        };
+        assert!(!escrow.on_funding_nft(Some(token(accounts(1)))));
+        assert!(escrow.on_funding_nft(Some(token(env::current_account_id()))));
+        assert!(matches!(escrow.state, EscrowState::Active));
+    }
+
+    #[test]
+    fn test_mt_escrow_funding_and_withdrawal() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Mt {
+            contract_id: "intents.near".parse().unwrap(),
+            token_id: "nep141:usdc.near".to_string(),
+        };
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+
+        testing_env!(get_context("intents.near".parse().unwrap()));
+        let refunds = escrow.mt_on_transfer(
+            accounts(1),
+            vec![accounts(1), accounts(1)],
+            vec!["nep141:usdc.near".to_string(), "nep141:wrap.near".to_string()],
+            vec![U128(500), U128(7)],
+            String::new(),
+        );
+        assert!(matches!(refunds, PromiseOrValue::Value(ref refunds) if refunds == &vec![U128(0), U128(7)]));
+        assert!(matches!(escrow.state, EscrowState::Active));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts[0].actions.iter().any(|action| matches!(
+            action,
+            near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. }
+                if method_name == b"mt_transfer"
+        )));
+    }
+
+    fn created_methods() -> Vec<Vec<u8>> {
+        near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. } => Some(method_name),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rescue_nft_left_after_payout() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, nft_immutables(hashlock), None);
+        // The payout transfer failed, so the NFT stayed on the withdrawn escrow
+        escrow.state = EscrowState::Withdrawn;
+
+        testing_env!(get_rescue_context(accounts(1), NearToken::from_near(1))); // maker
+        let _ = escrow.rescue_funds(nft_immutables(String::new()).asset, U128(1));
+        assert_eq!(created_methods(), vec![b"nft_transfer".to_vec()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot rescue the NFT owed by the escrow")]
+    fn test_rescue_rejects_escrowed_nft() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, nft_immutables(hashlock), None);
+        escrow.state = EscrowState::Active;
+
+        testing_env!(get_rescue_context(accounts(1), NearToken::from_near(1)));
+        let _ = escrow.rescue_funds(nft_immutables(String::new()).asset, U128(1));
+    }
+
+    #[test]
+    fn test_rescue_multi_token() {
+        testing_env!(get_context(accounts(0)));
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock("test_secret_123"));
+        let escrowed = EscrowAsset::Mt {
+            contract_id: "intents.near".parse().unwrap(),
+            token_id: "nep141:usdc.near".to_string(),
+        };
+        immutables.asset = escrowed.clone();
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        escrow.state = EscrowState::Active;
+
+        // A stray token is moved directly
+        testing_env!(get_rescue_context(accounts(1), NearToken::from_near(1)));
+        let stray = EscrowAsset::Mt {
+            contract_id: "intents.near".parse().unwrap(),
+            token_id: "nep141:wrap.near".to_string(),
+        };
+        let _ = escrow.rescue_funds(stray, U128(7));
+        assert_eq!(created_methods(), vec![b"mt_transfer".to_vec()]);
+
+        // The escrowed token is checked against the balance first
+        testing_env!(get_rescue_context(accounts(1), NearToken::from_near(1)));
+        let _ = escrow.rescue_funds(escrowed.clone(), U128(7));
+        assert_eq!(created_methods(), vec![b"mt_balance_of".to_vec(), b"on_rescue_balance".to_vec()]);
+
+        testing_env!(get_context(accounts(0)));
+        let _ = escrow.on_rescue_balance(escrowed, U128(7), accounts(1), U128(507));
+        assert_eq!(created_methods(), vec![b"mt_transfer".to_vec()]);
+    }
+
+    #[test]
+    fn test_token_payout_checks_recipient_storage() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        escrow.on_funding_balance(U128(500));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        assert!(created_methods().contains(&b"storage_balance_of".to_vec()));
+    }
+
+    #[test]
+    fn test_unregistered_recipient_is_registered_before_payout() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+        escrow.state = EscrowState::Withdrawn;
+
+        let _ = escrow.on_recipient_storage(accounts(4), accounts(2), U128(500), None, Ok(None));
+        assert_eq!(
+            created_methods(),
+            vec![b"storage_deposit".to_vec(), b"ft_transfer".to_vec(), b"on_token_payout".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_registered_recipient_is_paid_directly() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        let registered = StorageBalance {
+            total: NearToken::from_millinear(2),
+            available: NearToken::from_near(0),
+        };
+        let _ = escrow.on_recipient_storage(accounts(4), accounts(2), U128(500), None, Ok(Some(registered)));
+        assert_eq!(created_methods(), vec![b"ft_transfer".to_vec(), b"on_token_payout".to_vec()]);
+    }
+
+    #[test]
+    fn test_failed_token_payout_is_kept_for_recipient() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut immutables = test_immutables(hashlock);
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        escrow.state = EscrowState::Withdrawn;
+
+        // A token without NEP145 support still gets the plain transfer
+        let _ = escrow.on_recipient_storage(accounts(4), accounts(2), U128(500), None, Err(near_sdk::PromiseError::Failed));
+        assert_eq!(created_methods(), vec![b"ft_transfer".to_vec(), b"on_token_payout".to_vec()]);
+
+        assert!(!escrow.on_token_payout(accounts(4), accounts(2), U128(500), Err(near_sdk::PromiseError::Failed)));
+        assert_eq!(escrow.get_unpaid_payout(accounts(2), accounts(4)), U128(500));
+        assert_eq!(escrow.owed_tokens(&accounts(4)), 500);
+
+        // The maker cannot rescue the unpaid tokens
+        testing_env!(get_rescue_context(accounts(1), NearToken::from_near(1)));
+        let _ = escrow.rescue_funds(EscrowAsset::Ft { contract_id: accounts(4) }, U128(500));
+        assert_eq!(created_methods(), vec![b"ft_balance_of".to_vec(), b"on_rescue_balance".to_vec()]);
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.claim_unpaid_payout(accounts(4));
+        assert_eq!(escrow.get_unpaid_payout(accounts(2), accounts(4)), U128(0));
+    }
+
+    #[test]
+    fn test_withdraw_with_payout_call() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        immutables.amount = 500;
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, None);
+        escrow.on_funding_balance(U128(500));
+
+        let payout_call = PayoutCall { receiver_id: "dex.near".parse().unwrap(), msg: "swap".to_string() };
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw_with_call(secret.to_string(), payout_call.clone());
+        assert!(matches!(escrow.state, EscrowState::Withdrawn));
+
+        assert!(created_methods().contains(&b"storage_balance_of".to_vec()));
+
+        // Storage callback, with the DEX already registered on the token
+        testing_env!(get_context(accounts(0)));
+        let registered = StorageBalance {
+            total: NearToken::from_millinear(2),
+            available: NearToken::from_near(0),
+        };
+        let _ = escrow.on_recipient_storage(accounts(4), accounts(2), U128(500), Some(payout_call), Ok(Some(registered)));
+        assert!(created_methods().contains(&b"ft_transfer_call".to_vec()));
+    }
+
+    #[test]
+    fn test_payout_call_refund_goes_to_recipient() {
+        testing_env!(get_context(accounts(0)));
+        let hashlock = CryptoUtils::create_hashlock("test_secret_123");
+        let mut escrow = Escrow::new(EscrowType::Destination, test_immutables(hashlock), None);
+
+        let used = escrow.on_payout_call_resolved(accounts(4), accounts(2), U128(500), Ok(U128(300)));
+        assert_eq!(used, U128(300));
+        // The 200 refunded tokens go back out to the recipient
+        assert!(created_methods().contains(&b"storage_balance_of".to_vec()));
+
+        let used = escrow.on_payout_call_resolved(
+            accounts(4),
+            accounts(2),
+            U128(500),
+            Err(near_sdk::PromiseError::Failed),
+        );
+        assert_eq!(used, U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the payout recipient can set a payout call")]
+    fn test_only_recipient_sets_payout_call() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Ft { contract_id: accounts(4) };
+        let mut escrow = Escrow::new(EscrowType::Source, immutables, None);
+
+        testing_env!(get_context(accounts(3))); // outsider
+        let payout_call = PayoutCall { receiver_id: "dex.near".parse().unwrap(), msg: String::new() };
+        escrow.withdraw_with_call(secret.to_string(), payout_call);
+    }
+
+    #[test]
+    fn test_wrapped_near_payout() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.payout_conversion = Some(NearConversion::Wrap);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, Some("wrap.near".parse().unwrap()));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        let methods = created_methods();
+        assert!(methods.contains(&b"near_deposit".to_vec()));
+        assert!(methods.contains(&b"on_near_wrapped".to_vec()));
+
+        // A failed wrap pays native NEAR instead
+        testing_env!(get_context(accounts(0)));
+        let _ = escrow.on_near_wrapped(accounts(2), U128(1000), Err(near_sdk::PromiseError::Failed));
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(matches!(receipts[0].actions[0], near_sdk::mock::MockAction::Transfer { .. }));
+    }
+
+    #[test]
+    fn test_unwrapped_near_payout() {
+        testing_env!(get_context(accounts(0)));
+        let secret = "test_secret_123";
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock(secret));
+        immutables.asset = EscrowAsset::Ft { contract_id: "wrap.near".parse().unwrap() };
+        immutables.payout_conversion = Some(NearConversion::Unwrap);
+        let mut escrow = Escrow::new(EscrowType::Destination, immutables, Some("wrap.near".parse().unwrap()));
+        escrow.on_funding_balance(U128(1000000000000000000000000));
+
+        testing_env!(get_context(accounts(2))); // taker
+        let _ = escrow.withdraw(secret.to_string());
+        assert!(created_methods().contains(&b"near_withdraw".to_vec()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid immutables")]
+    fn test_conversion_requires_wnear_account() {
+        testing_env!(get_context(accounts(0)));
+        let mut immutables = test_immutables(CryptoUtils::create_hashlock("test_secret_123"));
+        immutables.payout_conversion = Some(NearConversion::Wrap);
+        Escrow::new(EscrowType::Destination, immutables, None);
+    }
+}
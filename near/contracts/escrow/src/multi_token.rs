@@ -0,0 +1,70 @@
+//! NEP-245 multi-token support.
+//!
+//! `near-contract-standards` does not ship NEP-245 yet, so the parts of the
+//! standard the escrow relies on are declared here: `mt_transfer` and
+//! `mt_balance_of` on the token contract, and the `mt_on_transfer` receiver.
+
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, log, near_bindgen, AccountId, PromiseOrValue};
+
+use shared::EscrowAsset;
+
+use crate::{Escrow, EscrowExt, EscrowState};
+
+/// NEP-245 token id
+pub type MtTokenId = String;
+
+/// NEP-245 multi-token contract
+#[ext_contract(ext_mt_core)]
+pub trait MultiTokenCore {
+    fn mt_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: MtTokenId,
+        amount: U128,
+        approval: Option<(AccountId, u64)>,
+        memo: Option<String>,
+    );
+
+    fn mt_balance_of(&self, account_id: AccountId, token_id: MtTokenId) -> U128;
+}
+
+#[near_bindgen]
+impl Escrow {
+    /// NEP-245 receiver: keep the escrowed token and return every other token to its sender.
+    /// A transfer covering the whole amount activates the escrow once its safety deposit is there.
+    pub fn mt_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_ids: Vec<AccountId>,
+        token_ids: Vec<MtTokenId>,
+        amounts: Vec<U128>,
+        msg: String,
+    ) -> PromiseOrValue<Vec<U128>> {
+        assert_eq!(token_ids.len(), amounts.len(), "Token ids and amounts must match");
+        let contract_id = env::predecessor_account_id();
+
+        let refunds = token_ids
+            .iter()
+            .zip(&amounts)
+            .map(|(token_id, amount)| {
+                let expected = EscrowAsset::Mt {
+                    contract_id: contract_id.clone(),
+                    token_id: token_id.clone(),
+                };
+                if self.immutables.asset != expected || !matches!(self.state, EscrowState::Pending) {
+                    log!("Returning {} of {} {} to {:?}", amount.0, contract_id, token_id, previous_owner_ids);
+                    return *amount;
+                }
+
+                log!("Received {} of {} {} from {} with msg: {}", amount.0, contract_id, token_id, sender_id, msg);
+                if amount.0 >= self.immutables.amount && self.has_near_funding() {
+                    self.state = EscrowState::Active;
+                    log!("Escrow funded with {} of {}", amount.0, token_id);
+                }
+                U128(0)
+            })
+            .collect();
+        PromiseOrValue::Value(refunds)
+    }
+}
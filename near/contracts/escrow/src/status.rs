@@ -0,0 +1,140 @@
+//! Single-call summary of an escrow for UIs and resolver bots.
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, AccountId, BlockHeight, Timestamp};
+use schemars::{gen::SchemaGenerator, schema::{Schema, SchemaObject}, JsonSchema};
+
+use shared::{EscrowAsset, EscrowType, TimelockClock, TimelockSchedule, TimelockStage};
+
+use crate::{Escrow, EscrowExt, EscrowState};
+
+/// Everything `get_status` reports about an escrow at the current block
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowStatus {
+    pub escrow_type: EscrowType,
+    pub state: EscrowState,
+    /// Block timestamp the status was computed at (nanoseconds)
+    pub now: Timestamp,
+    /// Block height the status was computed at
+    pub block_height: BlockHeight,
+    pub deployed_at: Timestamp,
+    pub deployed_at_height: BlockHeight,
+    /// Clock the stages are measured in
+    pub clock: TimelockClock,
+    pub stage: TimelockStage,
+    /// Stage boundaries as block timestamps and block heights, converted from `clock`
+    pub stages: TimelockSchedule,
+    /// Seconds until the current stage ends, rounded up (at least, on the block-height clock);
+    /// `None` once rescue is allowed
+    pub seconds_to_next_stage: Option<u64>,
+    /// Blocks until the current stage ends (at most, on the timestamp clock);
+    /// `None` once rescue is allowed
+    pub blocks_to_next_stage: Option<u64>,
+    /// Account that can withdraw right now (for source escrows anyone may trigger it for the maker)
+    pub withdrawer: Option<AccountId>,
+    /// Account that can cancel right now
+    pub canceller: Option<AccountId>,
+    /// Account that can rescue stray funds right now
+    pub rescuer: Option<AccountId>,
+    pub asset: EscrowAsset,
+    /// Escrowed amount still held, zero once withdrawn or cancelled
+    pub amount: U128,
+    /// Safety deposit still held, zero once withdrawn or cancelled
+    pub safety_deposit: U128,
+    /// NEAR balance of the escrow account, including storage
+    pub near_balance: U128,
+}
+
+impl JsonSchema for EscrowStatus {
+    fn schema_name() -> String {
+        "EscrowStatus".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("escrow_type".to_string(), gen.subschema_for::<EscrowType>());
+        schema.object().properties.insert("state".to_string(), gen.subschema_for::<EscrowState>());
+        schema.object().properties.insert("now".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("block_height".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("deployed_at".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("deployed_at_height".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("clock".to_string(), gen.subschema_for::<TimelockClock>());
+        schema.object().properties.insert("stage".to_string(), gen.subschema_for::<TimelockStage>());
+        schema.object().properties.insert("stages".to_string(), gen.subschema_for::<TimelockSchedule>());
+        schema.object().properties.insert("seconds_to_next_stage".to_string(), gen.subschema_for::<Option<u64>>());
+        schema.object().properties.insert("blocks_to_next_stage".to_string(), gen.subschema_for::<Option<u64>>());
+        schema.object().properties.insert("withdrawer".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("canceller".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("rescuer".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("asset".to_string(), gen.subschema_for::<EscrowAsset>());
+        schema.object().properties.insert("amount".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("safety_deposit".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("near_balance".to_string(), gen.subschema_for::<String>());
+        schema.object().required.extend(vec![
+            "escrow_type".to_string(),
+            "state".to_string(),
+            "now".to_string(),
+            "block_height".to_string(),
+            "deployed_at".to_string(),
+            "deployed_at_height".to_string(),
+            "clock".to_string(),
+            "stage".to_string(),
+            "stages".to_string(),
+            "seconds_to_next_stage".to_string(),
+            "blocks_to_next_stage".to_string(),
+            "withdrawer".to_string(),
+            "canceller".to_string(),
+            "rescuer".to_string(),
+            "asset".to_string(),
+            "amount".to_string(),
+            "safety_deposit".to_string(),
+            "near_balance".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+#[near_bindgen]
+impl Escrow {
+    /// State, timing, permitted actors and balances of the escrow in one view
+    pub fn get_status(&self) -> EscrowStatus {
+        let timelocks = &self.timelocks;
+        let reading = timelocks.clock.now();
+        let stages = self.get_timelock_stages();
+        let remaining = stages.native().next_boundary(reading).map(|boundary| boundary - reading);
+        let converted = remaining.map(|ticks| timelocks.clock.ticks_on_other_clock(ticks));
+        let (nanos_to_next_stage, blocks_to_next_stage) = match timelocks.clock {
+            TimelockClock::Timestamp => (remaining, converted),
+            TimelockClock::BlockHeight => (converted, remaining),
+        };
+        let seconds_to_next_stage = nanos_to_next_stage.map(|nanos| nanos.div_ceil(1_000_000_000));
+        let (amount, safety_deposit) = if self.is_open() {
+            (self.immutables.amount, self.immutables.safety_deposit)
+        } else {
+            (0, 0)
+        };
+
+        EscrowStatus {
+            escrow_type: self.escrow_type.clone(),
+            state: self.state.clone(),
+            now: env::block_timestamp(),
+            block_height: env::block_height(),
+            deployed_at: timelocks.deployed_at,
+            deployed_at_height: timelocks.deployed_at_height,
+            clock: timelocks.clock,
+            stage: stages.native().stage_at(reading),
+            stages,
+            seconds_to_next_stage,
+            blocks_to_next_stage,
+            withdrawer: self.can_withdraw().then(|| self.get_withdraw_authority()),
+            canceller: self.can_cancel().then(|| self.get_cancel_authority()),
+            rescuer: self.can_rescue().then(|| self.get_cancel_authority()),
+            asset: self.immutables.asset.clone(),
+            amount: U128(amount),
+            safety_deposit: U128(safety_deposit),
+            near_balance: U128(env::account_balance().as_yoctonear()),
+        }
+    }
+}
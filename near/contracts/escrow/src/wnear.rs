@@ -0,0 +1,95 @@
+//! Native NEAR ↔ wNEAR conversion of withdrawal payouts.
+//!
+//! With `payout_conversion` set, the escrow wraps its native NEAR with
+//! `near_deposit` or unwraps its wNEAR with `near_withdraw` on the wNEAR contract
+//! configured in the factory before paying the recipient. If the conversion
+//! fails, the recipient is paid in the asset the escrow holds instead.
+
+use near_sdk::json_types::U128;
+use near_sdk::{env, ext_contract, log, near_bindgen, AccountId, Gas, NearToken, Promise, PromiseError};
+
+use near_contract_standards::storage_management::ext_storage_management;
+use shared::NearConversion;
+
+use crate::{Escrow, EscrowExt, GAS_FOR_STORAGE_DEPOSIT, STORAGE_REGISTRATION_DEPOSIT};
+
+/// Gas for `near_deposit` / `near_withdraw` on the wNEAR contract
+const GAS_FOR_WNEAR_CALL: Gas = Gas::from_gas(10_000_000_000_000);
+/// Gas for the callback paying out the converted amount
+const GAS_FOR_CONVERSION_CALLBACK: Gas = Gas::from_gas(60_000_000_000_000);
+
+/// wNEAR contract
+#[ext_contract(ext_wnear)]
+pub trait WrappedNear {
+    fn near_deposit(&mut self);
+    fn near_withdraw(&mut self, amount: U128);
+}
+
+#[near_bindgen]
+impl Escrow {
+    pub fn get_wnear_account(&self) -> Option<AccountId> {
+        self.wnear_account.clone()
+    }
+
+    /// Callback paying out wNEAR once `near_deposit` succeeded, native NEAR otherwise
+    #[private]
+    pub fn on_near_wrapped(
+        &mut self,
+        recipient: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> Promise {
+        if result.is_err() {
+            log!("Wrapping failed, paying {} yoctoNEAR to {}", amount.0, recipient);
+            return Promise::new(recipient).transfer(NearToken::from_yoctonear(amount.0));
+        }
+        self.transfer_token(self.wnear(), recipient, amount.0)
+    }
+
+    /// Callback paying out native NEAR once `near_withdraw` succeeded, wNEAR otherwise
+    #[private]
+    pub fn on_near_unwrapped(
+        &mut self,
+        recipient: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> Promise {
+        if result.is_err() {
+            log!("Unwrapping failed, paying {} wNEAR to {}", amount.0, recipient);
+            return self.transfer_token(self.wnear(), recipient, amount.0);
+        }
+        Promise::new(recipient).transfer(NearToken::from_yoctonear(amount.0))
+    }
+}
+
+impl Escrow {
+    /// Pay the escrowed amount to `recipient`, converted as the maker asked in `payout_conversion`
+    pub(crate) fn transfer_converted(&self, recipient: AccountId, conversion: &NearConversion) -> Promise {
+        let wnear = self.wnear();
+        let amount = self.immutables.amount;
+        let callback = Self::ext(env::current_account_id()).with_static_gas(GAS_FOR_CONVERSION_CALLBACK);
+        match conversion {
+            NearConversion::Wrap => ext_storage_management::ext(wnear.clone())
+                // Register the escrow itself so the whole amount is wrapped
+                .with_static_gas(GAS_FOR_STORAGE_DEPOSIT)
+                .with_attached_deposit(NearToken::from_yoctonear(STORAGE_REGISTRATION_DEPOSIT))
+                .storage_deposit(Some(env::current_account_id()), Some(true))
+                .then(
+                    ext_wnear::ext(wnear)
+                        .with_static_gas(GAS_FOR_WNEAR_CALL)
+                        .with_attached_deposit(NearToken::from_yoctonear(amount))
+                        .near_deposit(),
+                )
+                .then(callback.on_near_wrapped(recipient, U128(amount))),
+            NearConversion::Unwrap => ext_wnear::ext(wnear)
+                .with_static_gas(GAS_FOR_WNEAR_CALL)
+                .with_attached_deposit(NearToken::from_yoctonear(1))
+                .near_withdraw(U128(amount))
+                .then(callback.on_near_unwrapped(recipient, U128(amount))),
+        }
+    }
+
+    fn wnear(&self) -> AccountId {
+        self.wnear_account.clone().expect("wNEAR account not configured")
+    }
+}
@@ -0,0 +1,245 @@
+//! Batch operations for resolvers filling many orders per block.
+//!
+//! `create_escrows_batch` creates several escrows from one deposit and reports
+//! which ones were created. Invalid items are skipped and their deposit refunded
+//! right away; failed creations are refunded by `on_escrow_created`.
+//! `batch_withdraw` and `batch_cancel` fan out to the escrows' factory-only
+//! `withdraw_for`/`cancel_for`, acting on behalf of the caller. Each call gets the
+//! gas its escrow's payout needs; batches that do not fit the attached gas are rejected.
+
+use near_sdk::{env, ext_contract, log, near_bindgen, AccountId, Gas, NearToken, Promise, PromiseOrValue, PromiseResult};
+
+use shared::{EscrowAsset, EscrowImmutables, EscrowType, SignedOrder};
+
+use crate::{EscrowFactory, EscrowFactoryExt, EscrowInfo, GAS_FOR_CALLBACK};
+
+/// Maximum number of escrows in one batch
+const MAX_BATCH_SIZE: usize = 5;
+/// Gas for the escrow's own execution of `withdraw_for` or `cancel_for`
+const GAS_FOR_ESCROW_EXECUTION: Gas = Gas::from_gas(15_000_000_000_000);
+/// Gas a withdrawing escrow attaches to reporting the secret to the factory
+const GAS_FOR_SECRET_REPORT: Gas = Gas::from_gas(10_000_000_000_000);
+/// Gas a NEP141 payout uses: storage lookup, transfer and its result callback
+const GAS_FOR_FT_PAYOUT: Gas = Gas::from_gas(55_000_000_000_000);
+/// Gas a NEP141 payout through the maker's `payout_call` uses, including its resolution
+const GAS_FOR_FT_PAYOUT_CALL: Gas = Gas::from_gas(225_000_000_000_000);
+/// Gas a NEP171 or NEP245 transfer uses
+const GAS_FOR_TOKEN_PAYOUT: Gas = Gas::from_gas(20_000_000_000_000);
+/// Gas a wNEAR wrap or unwrap payout uses, including its callback
+const GAS_FOR_CONVERTED_PAYOUT: Gas = Gas::from_gas(80_000_000_000_000);
+/// Gas refunding a pending NEP141 or NEP245 escrow uses: balance query and refund callback
+const GAS_FOR_PENDING_REFUND: Gas = Gas::from_gas(75_000_000_000_000);
+
+/// Escrow methods the factory calls on behalf of a party
+#[ext_contract(ext_escrow)]
+pub trait FactoryRoutedEscrow {
+    fn withdraw_for(&mut self, caller: AccountId, secret: String);
+    fn cancel_for(&mut self, caller: AccountId);
+}
+
+#[near_bindgen]
+impl EscrowFactory {
+    /// Create several escrows in one transaction. The attached deposit must cover the
+    /// required deposit of every item; the excess is refunded right away. Invalid items are
+    /// skipped and their deposit refunded with the excess. Each item may carry the maker's
+    /// signed order, required when signed orders are enforced. Resolves to whether each
+    /// escrow was created, in order.
+    #[payable]
+    pub fn create_escrows_batch(
+        &mut self,
+        escrows: Vec<(EscrowType, EscrowImmutables, Option<SignedOrder>)>,
+    ) -> PromiseOrValue<Vec<(AccountId, bool)>> {
+        self.assert_migrated();
+        assert_batch_size(escrows.len());
+        let creator = env::predecessor_account_id();
+        let attached = env::attached_deposit().as_yoctonear();
+        let deposits: Vec<_> = escrows
+            .iter()
+            .map(|(_, immutables, _)| self.calculate_required_deposit(immutables))
+            .collect();
+        let required: u128 = deposits.iter().sum();
+        assert!(
+            attached >= required,
+            "Insufficient deposit. Required: {}, provided: {}",
+            required, attached
+        );
+
+        let mut items = Vec::with_capacity(escrows.len());
+        let mut batch: Option<Promise> = None;
+        let mut used = 0;
+        for ((escrow_type, immutables, signed_order), deposit) in escrows.into_iter().zip(deposits) {
+            let account = shared::escrow_account_id(
+                &env::current_account_id(),
+                &immutables.order_hash,
+                immutables.fill_index,
+                &escrow_type,
+            );
+            match self.try_create_escrow(immutables, escrow_type, signed_order, deposit) {
+                Ok(creation) => {
+                    used += deposit;
+                    batch = Some(match batch {
+                        Some(batch) => batch.and(creation),
+                        None => creation,
+                    });
+                    items.push((account, true));
+                }
+                Err(error) => {
+                    log!("Batch item {} skipped: {}", account, error);
+                    items.push((account, false));
+                }
+            }
+        }
+
+        if attached > used {
+            Promise::new(creator).transfer(NearToken::from_yoctonear(attached - used));
+        }
+
+        match batch {
+            Some(batch) => PromiseOrValue::Promise(
+                batch.then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_CALLBACK)
+                        .on_batch_created(items),
+                ),
+            ),
+            None => PromiseOrValue::Value(items),
+        }
+    }
+
+    /// Withdraw from several escrows as the caller, each with its secret
+    pub fn batch_withdraw(&mut self, withdrawals: Vec<(AccountId, String)>) -> Promise {
+        self.assert_migrated();
+        assert_batch_size(withdrawals.len());
+        let caller = env::predecessor_account_id();
+        let accounts: Vec<_> = withdrawals.iter().map(|(escrow, _)| escrow.clone()).collect();
+        let gas = self.batch_gas(&accounts, true);
+        let calls = withdrawals.into_iter().zip(gas).map(|((escrow, secret), gas)| {
+            ext_escrow::ext(escrow)
+                .with_static_gas(gas)
+                .withdraw_for(caller.clone(), secret)
+        });
+        self.settle_batch(calls, accounts)
+    }
+
+    /// Cancel several escrows as the caller
+    pub fn batch_cancel(&mut self, escrows: Vec<AccountId>) -> Promise {
+        self.assert_migrated();
+        assert_batch_size(escrows.len());
+        let caller = env::predecessor_account_id();
+        let gas = self.batch_gas(&escrows, false);
+        let calls = escrows.iter().zip(gas).map(|(escrow, gas)| {
+            ext_escrow::ext(escrow.clone())
+                .with_static_gas(gas)
+                .cancel_for(caller.clone())
+        });
+        self.settle_batch(calls, escrows.clone())
+    }
+
+    /// Joined callback of a batch: whether the call on each escrow succeeded, in order
+    #[private]
+    pub fn on_batch_settled(&mut self, escrows: Vec<AccountId>) -> Vec<(AccountId, bool)> {
+        escrows
+            .into_iter()
+            .enumerate()
+            .map(|(index, escrow)| {
+                let succeeded = batch_item_succeeded(index as u64, &escrow);
+                (escrow, succeeded)
+            })
+            .collect()
+    }
+
+    /// Joined callback of a batch creation. `escrows` lists every item with whether its
+    /// creation was started; only started items have a promise result, in order.
+    #[private]
+    pub fn on_batch_created(&mut self, escrows: Vec<(AccountId, bool)>) -> Vec<(AccountId, bool)> {
+        let mut result_index = 0;
+        escrows
+            .into_iter()
+            .map(|(escrow, started)| {
+                if !started {
+                    return (escrow, false);
+                }
+                let created = batch_item_succeeded(result_index, &escrow);
+                result_index += 1;
+                (escrow, created)
+            })
+            .collect()
+    }
+}
+
+impl EscrowFactory {
+    fn settle_batch(&self, calls: impl Iterator<Item = Promise>, escrows: Vec<AccountId>) -> Promise {
+        calls
+            .reduce(|batch, call| batch.and(call))
+            .expect("Batch is empty")
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_CALLBACK)
+                    .on_batch_settled(escrows),
+            )
+    }
+
+    /// Gas for each escrow call of a batch withdraw (or cancel), rejecting batches whose
+    /// calls do not fit in the gas left to this call
+    fn batch_gas(&self, escrows: &[AccountId], withdraw: bool) -> Vec<Gas> {
+        let gas: Vec<_> = escrows
+            .iter()
+            .map(|escrow| {
+                let info = self
+                    .escrow_info
+                    .get(escrow)
+                    .unwrap_or_else(|| env::panic_str(&format!("Unknown escrow: {}", escrow)));
+                batch_item_gas(&info, withdraw)
+            })
+            .collect();
+        let required = gas
+            .iter()
+            .fold(GAS_FOR_CALLBACK, |total, item| total.saturating_add(*item));
+        let available = env::prepaid_gas().saturating_sub(env::used_gas());
+        assert!(
+            required <= available,
+            "Batch needs {} TGas but only {} TGas is left; attach more gas or split the batch",
+            required.as_tgas(), available.as_tgas()
+        );
+        gas
+    }
+}
+
+/// Gas an escrow needs for `withdraw_for` or `cancel_for`, sized by its asset and payout
+fn batch_item_gas(info: &EscrowInfo, withdraw: bool) -> Gas {
+    let immutables = &info.immutables;
+    let payout = match (&immutables.asset, withdraw) {
+        (EscrowAsset::Near, true) if immutables.payout_conversion.is_some() => GAS_FOR_CONVERTED_PAYOUT,
+        (EscrowAsset::Near, _) => Gas::from_gas(0),
+        // Only source withdrawals pay the maker through its payout call
+        (EscrowAsset::Ft { .. }, true)
+            if matches!(info.escrow_type, EscrowType::Source) && immutables.payout_call.is_some() =>
+        {
+            GAS_FOR_FT_PAYOUT_CALL
+        }
+        (EscrowAsset::Ft { .. }, true) => GAS_FOR_FT_PAYOUT,
+        // A cancel may refund a pending escrow, which queries its balance first
+        (EscrowAsset::Ft { .. } | EscrowAsset::Mt { .. }, false) => GAS_FOR_PENDING_REFUND,
+        (EscrowAsset::Mt { .. } | EscrowAsset::Nft { .. }, _) => GAS_FOR_TOKEN_PAYOUT,
+    };
+    let report = if withdraw { GAS_FOR_SECRET_REPORT } else { Gas::from_gas(0) };
+    GAS_FOR_ESCROW_EXECUTION.saturating_add(payout).saturating_add(report)
+}
+
+/// Whether the batch call at `index` succeeded. Creations resolve to `on_escrow_created`'s
+/// result, other calls only need to succeed.
+fn batch_item_succeeded(index: u64, escrow: &AccountId) -> bool {
+    let succeeded = match env::promise_result(index) {
+        PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<bool>(&value).unwrap_or(true),
+        PromiseResult::Failed => false,
+    };
+    log!("Batch item {}: {}", escrow, if succeeded { "succeeded" } else { "failed" });
+    succeeded
+}
+
+fn assert_batch_size(size: usize) {
+    assert!(
+        size > 0 && size <= MAX_BATCH_SIZE,
+        "Batch must contain between 1 and {} items", MAX_BATCH_SIZE
+    );
+}
@@ -1,19 +1,43 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::collections::{LazyOption, LookupMap, LookupSet, UnorderedMap};
 use near_sdk::json_types::U128;
 use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
 use near_sdk::{
     env, near_bindgen, AccountId, Gas, Promise, PromiseResult, NearToken,
-    PanicOnDefault, log,
+    PanicOnDefault, StorageUsage, log,
 };
 
-use shared::{EscrowImmutables, EscrowType, Balance};
+use shared::eip712::normalize_hex;
+use shared::{
+    emit_event, escrow_account_id, Balance, Eip712Domain, EscrowImmutables, EscrowType,
+    FusionOrder, SignedOrder,
+};
 use schemars::{JsonSchema, gen::SchemaGenerator, schema::{Schema, SchemaObject}};
 
+mod batch;
+mod migration;
+mod same_chain;
+mod token_registry;
+
+pub use batch::{ext_escrow, FactoryRoutedEscrow};
+pub use same_chain::{NearSwap, NearSwapTerms};
+pub use token_registry::{SupportedToken, TokenConfig};
+use migration::LegacyEscrows;
+
 /// Gas allocation for escrow contract calls
 const GAS_FOR_ESCROW_CALL: Gas = Gas::from_gas(30_000_000_000_000);
 /// Minimum storage deposit for escrow creation
 const MIN_STORAGE_DEPOSIT: Balance = 3_000_000_000_000_000_000_000_000; // 3 NEAR
+/// Gas for factory callbacks
+const GAS_FOR_CALLBACK: Gas = Gas::from_gas(10_000_000_000_000);
+/// Prefix of pre-deployed escrow accounts, kept apart from the `src-`/`dst-` order accounts
+const PREDEPLOYED_PREFIX: &str = "pre-";
+
+/// `order_to_escrow` key of one leg of an order fill, with the order hash normalized
+pub(crate) fn order_key(order_hash: &str, fill_index: u64, escrow_type: &EscrowType) -> (String, u64, EscrowType) {
+    (normalize_hex(order_hash), fill_index, escrow_type.clone())
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -45,13 +69,73 @@ impl JsonSchema for EscrowInfo {
     }
 }
 
+/// Escrow accounts created for both legs of an order
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OrderEscrows {
+    pub source: Option<AccountId>,
+    pub destination: Option<AccountId>,
+}
+
+impl JsonSchema for OrderEscrows {
+    fn schema_name() -> String {
+        "OrderEscrows".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("source".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("destination".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().required.extend(vec![
+            "source".to_string(),
+            "destination".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+/// What the factory deployed to an escrow account, for auditors and resolvers.
+/// Can be cross-checked off-chain with the `view_code` and `view_access_key_list` RPC queries.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EscrowDeploymentProof {
+    pub account_id: AccountId,
+    /// Base58 SHA-256 hash of the code deployed by the factory
+    pub code_hash: String,
+    /// Whether `code_hash` is the currently approved escrow code
+    pub approved: bool,
+    /// Access keys added by the factory (always empty, escrow accounts are locked)
+    pub access_keys: Vec<String>,
+}
+
+impl JsonSchema for EscrowDeploymentProof {
+    fn schema_name() -> String {
+        "EscrowDeploymentProof".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("account_id".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("code_hash".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("approved".to_string(), gen.subschema_for::<bool>());
+        schema.object().properties.insert("access_keys".to_string(), gen.subschema_for::<Vec<String>>());
+        schema.object().required.extend(vec![
+            "account_id".to_string(),
+            "code_hash".to_string(),
+            "approved".to_string(),
+            "access_keys".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct EscrowFactory {
     /// Owner of the factory contract
     pub owner: AccountId,
-    /// Map from order hash to escrow account ID
-    pub order_to_escrow: LookupMap<String, AccountId>,
+    /// Map from normalized order hash, fill index and escrow type to escrow account ID
+    pub order_to_escrow: LookupMap<(String, u64, EscrowType), AccountId>,
     /// Map from escrow account ID to escrow info
     pub escrow_info: UnorderedMap<AccountId, EscrowInfo>,
     /// Creation fee in yoctoNEAR
@@ -60,6 +144,38 @@ pub struct EscrowFactory {
     pub treasury: Option<AccountId>,
     /// Pre-deployed escrow contract account ID (used as template)
     pub escrow_template: Option<AccountId>,
+    /// EIP-712 domain of the EVM Limit Order Protocol that makers sign orders for
+    pub order_domain: Option<Eip712Domain>,
+    /// Whether escrow creation requires a maker-signed EVM order
+    pub require_signed_orders: bool,
+    /// Orders cancelled by their maker before an escrow was created
+    pub invalidated_orders: LookupSet<(AccountId, String)>,
+    /// Per-maker nonce; only signed orders whose `makerTraits` carry the current nonce are valid
+    pub maker_nonces: LookupMap<AccountId, u64>,
+    /// Approved escrow contract code deployed to new escrow accounts
+    pub escrow_code: LazyOption<Vec<u8>>,
+    /// Base58 SHA-256 hash of the approved escrow code
+    pub escrow_code_hash: Option<String>,
+    /// Escrow accounts deployed by this factory, with the code hash they run
+    pub deployed_escrows: LookupMap<AccountId, String>,
+    /// Open same-chain NEAR↔NEAR swaps by ID
+    pub near_swaps: LookupMap<u64, NearSwap>,
+    /// ID assigned to the next same-chain swap
+    pub next_swap_id: u64,
+    /// Secrets revealed by escrow withdrawals, by normalized order hash and fill index
+    pub revealed_secrets: LookupMap<(String, u64), String>,
+    /// Hashlocks already used by a maker's escrows, with the order hash and fill index that used them
+    pub used_hashlocks: LookupMap<(AccountId, String), (String, u64)>,
+    /// wNEAR contract escrows use to wrap or unwrap NEAR payouts
+    pub wnear_account: Option<AccountId>,
+    /// NEP-141 tokens escrows may hold, with their limits and metadata
+    pub supported_tokens: UnorderedMap<AccountId, TokenConfig>,
+    /// Same-chain swap payouts whose transfer failed, by recipient and token (None for NEAR)
+    pub unclaimed_swap_payouts: LookupMap<(AccountId, Option<AccountId>), Balance>,
+    /// Prepaid storage for swaps opened with `ft_transfer_call`, by maker
+    pub swap_storage_deposits: LookupMap<AccountId, Balance>,
+    /// Escrows of the original layout still to be moved by `migrate_escrows`
+    pub legacy_escrows: Option<LegacyEscrows>,
 }
 
 #[near_bindgen]
@@ -73,11 +189,28 @@ impl EscrowFactory {
     ) -> Self {
         Self {
             owner,
-            order_to_escrow: LookupMap::new(b"o"),
-            escrow_info: UnorderedMap::new(b"e"),
+            // The original order-hash-keyed maps lived under b"o" and b"e", see `migrate`
+            order_to_escrow: LookupMap::new(b"f"),
+            escrow_info: UnorderedMap::new(b"x"),
             creation_fee: creation_fee.0,
             treasury,
             escrow_template,
+            order_domain: None,
+            require_signed_orders: false,
+            invalidated_orders: LookupSet::new(b"i"),
+            maker_nonces: LookupMap::new(b"n"),
+            escrow_code: LazyOption::new(b"c", None),
+            escrow_code_hash: None,
+            deployed_escrows: LookupMap::new(b"d"),
+            near_swaps: LookupMap::new(b"s"),
+            next_swap_id: 0,
+            revealed_secrets: LookupMap::new(b"r"),
+            used_hashlocks: LookupMap::new(b"h"),
+            wnear_account: None,
+            supported_tokens: UnorderedMap::new(b"t"),
+            unclaimed_swap_payouts: LookupMap::new(b"u"),
+            swap_storage_deposits: LookupMap::new(b"g"),
+            legacy_escrows: None,
         }
     }
 
@@ -89,42 +222,91 @@ impl EscrowFactory {
         log!("Escrow template updated to: {}", template_clone);
     }
 
+    /// Store the approved escrow contract code, passed as raw input bytes (owner only)
+    pub fn set_escrow_code(&mut self) {
+        self.assert_owner();
+        let code = env::input().expect("Escrow code not provided");
+        let code_hash = near_sdk::bs58::encode(env::sha256(&code)).into_string();
+        self.escrow_code.set(&code);
+        self.escrow_code_hash = Some(code_hash.clone());
+        log!("Escrow code updated, code hash: {}", code_hash);
+    }
+
+    /// Update the EIP-712 domain used to verify maker-signed orders (owner only)
+    pub fn set_order_domain(&mut self, domain: Option<Eip712Domain>) {
+        self.assert_owner();
+        self.order_domain = domain.clone();
+        log!("Order domain updated to: {:?}", domain);
+    }
+
+    /// Require a maker-signed EVM order for every escrow creation (owner only)
+    pub fn set_require_signed_orders(&mut self, required: bool) {
+        self.assert_owner();
+        assert!(
+            !required || self.order_domain.is_some(),
+            "Order domain must be set before requiring signed orders"
+        );
+        self.require_signed_orders = required;
+        log!("Signed orders required: {}", required);
+    }
+
     /// Create a source escrow for EVM→NEAR swaps
     #[payable]
-    pub fn create_src_escrow(&mut self, immutables: EscrowImmutables) -> Promise {
-        self.create_escrow(immutables, EscrowType::Source)
+    pub fn create_src_escrow(
+        &mut self,
+        immutables: EscrowImmutables,
+        signed_order: Option<SignedOrder>,
+    ) -> Promise {
+        self.create_escrow(immutables, EscrowType::Source, signed_order, env::attached_deposit().as_yoctonear())
     }
 
     /// Create a destination escrow for NEAR→EVM swaps  
     #[payable]
-    pub fn create_dst_escrow(&mut self, immutables: EscrowImmutables) -> Promise {
-        self.create_escrow(immutables, EscrowType::Destination)
+    pub fn create_dst_escrow(
+        &mut self,
+        immutables: EscrowImmutables,
+        signed_order: Option<SignedOrder>,
+    ) -> Promise {
+        self.create_escrow(immutables, EscrowType::Destination, signed_order, env::attached_deposit().as_yoctonear())
     }
 
-    /// Internal escrow creation logic
-    fn create_escrow(&mut self, immutables: EscrowImmutables, escrow_type: EscrowType) -> Promise {
-        // Ensure template is set
-        let template = self.escrow_template.as_ref()
-            .expect("Escrow template not set");
+    /// Internal escrow creation logic, funded with `deposit` of the attached NEAR
+    fn create_escrow(
+        &mut self,
+        immutables: EscrowImmutables,
+        escrow_type: EscrowType,
+        signed_order: Option<SignedOrder>,
+        deposit: Balance,
+    ) -> Promise {
+        self.try_create_escrow(immutables, escrow_type, signed_order, deposit)
+            .unwrap_or_else(|e| env::panic_str(&e))
+    }
 
-        // Validate payment
-        let attached_deposit = env::attached_deposit();
-        let required_deposit = self.calculate_required_deposit(&immutables);
-        
-        assert!(
-            attached_deposit.as_yoctonear() >= required_deposit,
-            "Insufficient deposit. Required: {}, provided: {}", 
-            required_deposit, attached_deposit.as_yoctonear()
-        );
+    /// Create an escrow, or return why it cannot be created without changing any state
+    pub(crate) fn try_create_escrow(
+        &mut self,
+        mut immutables: EscrowImmutables,
+        escrow_type: EscrowType,
+        signed_order: Option<SignedOrder>,
+        deposit: Balance,
+    ) -> Result<Promise, String> {
+        // Ensure approved escrow code is set
+        let code_hash = self.escrow_code_hash.clone().ok_or("Escrow code not set")?;
 
-        // Check if escrow already exists for this order
-        assert!(
-            !self.order_to_escrow.contains_key(&immutables.order_hash),
-            "Escrow already exists for order: {}", immutables.order_hash
+        // Generate deterministic escrow account ID
+        let escrow_account_id = escrow_account_id(
+            &env::current_account_id(),
+            &immutables.order_hash,
+            immutables.fill_index,
+            &escrow_type,
         );
+        self.check_new_escrow(&mut immutables, &escrow_type, signed_order.as_ref(), deposit, &escrow_account_id)?;
 
-        // Generate deterministic escrow account ID
-        let escrow_account_id = self.generate_escrow_account_id(&immutables.order_hash, &escrow_type);
+        // A revealed secret must not unlock escrows of other orders
+        self.reserve_hashlock(&immutables);
+
+        // The escrow stamps its own deployment block; record the creation block here
+        immutables.timelocks.stamp_deployment();
         
         // Store escrow info
         let escrow_info = EscrowInfo {
@@ -135,13 +317,12 @@ impl EscrowFactory {
         };
 
         let order_hash_clone = immutables.order_hash.clone();
-        self.order_to_escrow.insert(&order_hash_clone, &escrow_account_id);
+        self.order_to_escrow.insert(&order_key(&order_hash_clone, immutables.fill_index, &escrow_type), &escrow_account_id);
         self.escrow_info.insert(&escrow_account_id, &escrow_info);
+        self.deployed_escrows.insert(&escrow_account_id, &code_hash);
 
         // Calculate amounts for escrow and fee
-        let escrow_amount = NearToken::from_yoctonear(
-            attached_deposit.as_yoctonear() - self.creation_fee
-        );
+        let escrow_amount = NearToken::from_yoctonear(deposit - self.creation_fee);
         
         log!(
             "Creating {} escrow: {} for order: {} with deposit: {}",
@@ -155,56 +336,117 @@ impl EscrowFactory {
         );
 
         // Create new account and initialize with unified escrow
-        Promise::new(escrow_account_id.clone())
+        Ok(Promise::new(escrow_account_id.clone())
             .create_account()
             .transfer(escrow_amount)
-            .add_full_access_key(env::signer_account_pk()) // Allow creator to manage
-            .deploy_contract(
-                // For now, we'll need to read the escrow WASM from the template
-                // In production, you'd store this differently or use cross-contract calls
-                self.get_escrow_wasm()
-            )
+            // No access keys: the escrow contract is the only code path on the account
+            .deploy_contract(self.get_escrow_wasm())
             .function_call(
                 "new".to_string(),
-                near_sdk::serde_json::to_vec(&(escrow_type, immutables)).unwrap(),
+                near_sdk::serde_json::to_vec(&(escrow_type, immutables, self.wnear_account.clone())).unwrap(),
                 NearToken::from_yoctonear(0),
                 GAS_FOR_ESCROW_CALL,
             )
             .then(
                 Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_gas(10_000_000_000_000))
+                    .with_static_gas(GAS_FOR_CALLBACK)
                     .on_escrow_created(
                         escrow_account_id,
                         order_hash_clone,
                         self.creation_fee,
+                        true,
+                        U128(deposit),
                     )
+            ))
+    }
+
+    /// Pre-deploy an uninitialized escrow account `pre-<name>.<factory>` running the approved code.
+    /// The account can later be registered for an order with `initialize_escrow`.
+    #[payable]
+    pub fn deploy_escrow_account(&mut self, name: String) -> Promise {
+        self.assert_migrated();
+        let code_hash = self.escrow_code_hash.clone().expect("Escrow code not set");
+        let attached_deposit = env::attached_deposit();
+        assert!(
+            attached_deposit.as_yoctonear() >= MIN_STORAGE_DEPOSIT,
+            "Insufficient deposit. Required: {}, provided: {}",
+            MIN_STORAGE_DEPOSIT, attached_deposit.as_yoctonear()
+        );
+
+        // Only direct sub-accounts of the factory can be created by it
+        assert!(!name.contains('.'), "Escrow account name must not contain '.'");
+        let escrow_account: AccountId = format!("{}{}.{}", PREDEPLOYED_PREFIX, name, env::current_account_id())
+            .parse()
+            .expect("Invalid escrow account name");
+        assert!(
+            !self.deployed_escrows.contains_key(&escrow_account),
+            "Escrow account {} already exists", escrow_account
+        );
+        self.deployed_escrows.insert(&escrow_account, &code_hash);
+
+        log!("Deploying escrow account: {} with code hash: {}", escrow_account, code_hash);
+
+        Promise::new(escrow_account.clone())
+            .create_account()
+            .transfer(attached_deposit)
+            .deploy_contract(self.get_escrow_wasm())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_CALLBACK)
+                    .on_escrow_deployed(escrow_account, env::predecessor_account_id(), U128(attached_deposit.as_yoctonear()))
             )
     }
 
-    /// Simple approach: Use a lightweight initialization pattern
-    /// Instead of deploying contracts, we'll use cross-contract calls to existing escrow contracts
+    /// Callback after an escrow account was pre-deployed.
+    /// On failure the `deposit` that came back to the factory is refunded to `payer`.
+    #[private]
+    pub fn on_escrow_deployed(&mut self, escrow_account: AccountId, payer: AccountId, deposit: U128) -> bool {
+        match env::promise_result(0) {
+            PromiseResult::Successful(_) => {
+                log!("Escrow account deployed: {}", escrow_account);
+                true
+            }
+            PromiseResult::Failed => {
+                log!("Failed to deploy escrow account: {}", escrow_account);
+                self.deployed_escrows.remove(&escrow_account);
+                if deposit.0 > 0 {
+                    Promise::new(payer.clone()).transfer(NearToken::from_yoctonear(deposit.0));
+                    log!("Refunded {} to {}", deposit.0, payer);
+                }
+                false
+            }
+        }
+    }
+
+    /// Initialize an escrow account pre-deployed by `deploy_escrow_account`.
+    /// Only factory-deployed accounts running the approved code hash are accepted.
     #[payable]
     pub fn initialize_escrow(
         &mut self, 
         escrow_account: AccountId,
         immutables: EscrowImmutables, 
-        escrow_type: EscrowType
+        escrow_type: EscrowType,
+        signed_order: Option<SignedOrder>,
     ) -> Promise {
-        // Validate payment
+        let mut immutables = immutables;
+        // Only accept accounts this factory deployed with the approved code
+        self.assert_approved_escrow(&escrow_account);
+
         let attached_deposit = env::attached_deposit();
-        let required_deposit = self.calculate_required_deposit(&immutables);
-        
-        assert!(
-            attached_deposit.as_yoctonear() >= required_deposit,
-            "Insufficient deposit. Required: {}, provided: {}", 
-            required_deposit, attached_deposit.as_yoctonear()
-        );
+        self.check_new_escrow(
+            &mut immutables,
+            &escrow_type,
+            signed_order.as_ref(),
+            attached_deposit.as_yoctonear(),
+            &escrow_account,
+        )
+        .unwrap_or_else(|e| env::panic_str(&e));
 
-        // Check if escrow already exists for this order
-        assert!(
-            !self.order_to_escrow.contains_key(&immutables.order_hash),
-            "Escrow already exists for order: {}", immutables.order_hash
-        );
+        // A revealed secret must not unlock escrows of other orders
+        self.reserve_hashlock(&immutables);
+
+        // The escrow stamps its own deployment block; record the creation block here
+        immutables.timelocks.stamp_deployment();
 
         // Store escrow info
         let escrow_info = EscrowInfo {
@@ -215,7 +457,7 @@ impl EscrowFactory {
         };
 
         let order_hash_clone = immutables.order_hash.clone();
-        self.order_to_escrow.insert(&order_hash_clone, &escrow_account);
+        self.order_to_escrow.insert(&order_key(&order_hash_clone, immutables.fill_index, &escrow_type), &escrow_account);
         self.escrow_info.insert(&escrow_account, &escrow_info);
 
         // Calculate amounts for escrow and fee
@@ -238,28 +480,34 @@ impl EscrowFactory {
             .transfer(escrow_amount)
             .function_call(
                 "new".to_string(),
-                near_sdk::serde_json::to_vec(&(escrow_type, immutables)).unwrap(),
+                near_sdk::serde_json::to_vec(&(escrow_type, immutables, self.wnear_account.clone())).unwrap(),
                 NearToken::from_yoctonear(0),
                 GAS_FOR_ESCROW_CALL,
             )
             .then(
                 Self::ext(env::current_account_id())
-                    .with_static_gas(Gas::from_gas(10_000_000_000_000))
+                    .with_static_gas(GAS_FOR_CALLBACK)
                     .on_escrow_created(
                         escrow_account,
                         order_hash_clone,
                         self.creation_fee,
+                        false,
+                        U128(attached_deposit.as_yoctonear()),
                     )
             )
     }
 
     /// Callback after escrow creation
+    /// `new_account` is false for pre-deployed accounts, which keep their code if initialization fails.
+    /// On failure the `deposit` paid for the escrow (fee included) is refunded to its creator.
     #[private]
     pub fn on_escrow_created(
         &mut self,
         escrow_account_id: AccountId,
         order_hash: String,
         fee: Balance,
+        new_account: bool,
+        deposit: U128,
     ) -> bool {
         match env::promise_result(0) {
             PromiseResult::Successful(_) => {
@@ -277,18 +525,119 @@ impl EscrowFactory {
             PromiseResult::Failed => {
                 log!("Failed to create escrow: {}", escrow_account_id);
                 
-                // Clean up storage
-                self.order_to_escrow.remove(&order_hash);
-                self.escrow_info.remove(&escrow_account_id);
+                // Clean up storage (pre-deployed accounts keep their code and stay reusable)
+                if new_account {
+                    self.deployed_escrows.remove(&escrow_account_id);
+                }
+                if let Some(info) = self.escrow_info.remove(&escrow_account_id) {
+                    self.order_to_escrow.remove(&order_key(&order_hash, info.immutables.fill_index, &info.escrow_type));
+                    self.release_hashlock(&info.immutables);
+
+                    // The failed receipt returned the escrow's NEAR to the factory
+                    if deposit.0 > 0 {
+                        Promise::new(info.creator.clone()).transfer(NearToken::from_yoctonear(deposit.0));
+                        log!("Refunded {} to {}", deposit.0, info.creator);
+                    }
+                }
                 
                 false
             }
         }
     }
 
-    /// Get escrow account ID for an order
-    pub fn get_escrow_for_order(&self, order_hash: String) -> Option<AccountId> {
-        self.order_to_escrow.get(&order_hash)
+    /// Cancel an order before an escrow exists for it (maker only).
+    /// The caller pays for the storage of the record; the rest of the deposit is refunded.
+    #[payable]
+    pub fn invalidate_order(&mut self, order_hash: String) {
+        self.assert_migrated();
+        let initial_storage = env::storage_usage();
+        let maker = env::predecessor_account_id();
+        self.invalidated_orders.insert(&(maker.clone(), normalize_hex(&order_hash)));
+        self.charge_storage(initial_storage);
+        log!("Order {} invalidated by maker {}", order_hash, maker);
+    }
+
+    /// Invalidate every signed order the caller issued under its current nonce.
+    /// The caller pays for the storage of the nonce; the rest of the deposit is refunded.
+    #[payable]
+    pub fn increase_nonce(&mut self) -> u64 {
+        self.assert_migrated();
+        let initial_storage = env::storage_usage();
+        let maker = env::predecessor_account_id();
+        let nonce = self.get_maker_nonce(maker.clone()) + 1;
+        self.maker_nonces.insert(&maker, &nonce);
+        self.charge_storage(initial_storage);
+        log!("Nonce of maker {} increased to {}", maker, nonce);
+        nonce
+    }
+
+    /// Whether the maker cancelled the order or, for a signed order, it was not issued under the
+    /// maker's current nonce. `nonce` is the nonce or epoch of the signed order's `makerTraits`;
+    /// orders created without a signed order only honour per-order invalidation.
+    pub fn is_order_invalidated(&self, maker: AccountId, order_hash: String, nonce: Option<u64>) -> bool {
+        nonce.is_some_and(|nonce| nonce != self.get_maker_nonce(maker.clone()))
+            || self.invalidated_orders.contains(&(maker, normalize_hex(&order_hash)))
+    }
+
+    /// Record the secret revealed by an escrow of this factory on withdrawal (escrows only)
+    pub fn report_secret(&mut self, secret: String) {
+        self.assert_migrated();
+        let escrow_account = env::predecessor_account_id();
+        let info = self
+            .escrow_info
+            .get(&escrow_account)
+            .expect("Only escrows created by this factory can report secrets");
+        assert!(
+            info.immutables.verify_secret(&secret),
+            "Invalid secret"
+        );
+
+        let key = (normalize_hex(&info.immutables.order_hash), info.immutables.fill_index);
+        if self.revealed_secrets.get(&key).is_some() {
+            return;
+        }
+        self.revealed_secrets.insert(&key, &secret);
+
+        emit_event("secret_revealed", json!({
+            "order_hash": info.immutables.order_hash,
+            "fill_index": info.immutables.fill_index,
+            "hashlock": info.immutables.hashlock,
+            "secret": secret,
+            "escrow": escrow_account,
+        }));
+    }
+
+    /// Whether an escrow of `maker` on this factory already uses `hashlock`
+    pub fn is_hashlock_used(&self, maker: AccountId, hashlock: String) -> bool {
+        self.used_hashlocks.contains_key(&(maker, normalize_hex(&hashlock)))
+    }
+
+    /// Secret revealed for an order fill, if any escrow has been withdrawn
+    pub fn get_revealed_secret(&self, order_hash: String, fill_index: u64) -> Option<String> {
+        self.revealed_secrets.get(&(normalize_hex(&order_hash), fill_index))
+    }
+
+    pub fn get_maker_nonce(&self, maker: AccountId) -> u64 {
+        self.maker_nonces.get(&maker).unwrap_or(0)
+    }
+
+    /// Get the source and destination escrow account IDs for a fill of an order (fill 0 by default)
+    pub fn get_escrow_for_order(&self, order_hash: String, fill_index: Option<u64>) -> OrderEscrows {
+        let fill_index = fill_index.unwrap_or(0);
+        OrderEscrows {
+            source: self.order_to_escrow.get(&order_key(&order_hash, fill_index, &EscrowType::Source)),
+            destination: self.order_to_escrow.get(&order_key(&order_hash, fill_index, &EscrowType::Destination)),
+        }
+    }
+
+    /// Get the escrow account ID for one leg of a fill of an order (fill 0 by default)
+    pub fn get_escrow_for_order_leg(
+        &self,
+        order_hash: String,
+        escrow_type: EscrowType,
+        fill_index: Option<u64>,
+    ) -> Option<AccountId> {
+        self.order_to_escrow.get(&order_key(&order_hash, fill_index.unwrap_or(0), &escrow_type))
     }
 
     /// Get escrow information
@@ -310,6 +659,13 @@ impl EscrowFactory {
         log!("Treasury updated to: {:?}", treasury);
     }
 
+    /// Set the wNEAR contract used for NEAR payout conversion (owner only)
+    pub fn set_wnear_account(&mut self, wnear_account: Option<AccountId>) {
+        self.assert_owner();
+        self.wnear_account = wnear_account.clone();
+        log!("wNEAR account updated to: {:?}", wnear_account);
+    }
+
     /// Emergency fund rescue (owner only)
     pub fn rescue_funds(&mut self, amount: U128, recipient: AccountId) -> Promise {
         self.assert_owner();
@@ -330,10 +686,51 @@ impl EscrowFactory {
         self.treasury.clone()
     }
 
+    pub fn get_wnear_account(&self) -> Option<AccountId> {
+        self.wnear_account.clone()
+    }
+
     pub fn get_escrow_template(&self) -> Option<AccountId> {
         self.escrow_template.clone()
     }
 
+    pub fn get_escrow_code_hash(&self) -> Option<String> {
+        self.escrow_code_hash.clone()
+    }
+
+    /// Whether the account was deployed by this factory and runs the approved escrow code
+    pub fn is_approved_escrow(&self, escrow_account: AccountId) -> bool {
+        self.escrow_code_hash.is_some()
+            && self.deployed_escrows.get(&escrow_account) == self.escrow_code_hash
+    }
+
+    /// Code hash and access keys the factory deployed to an escrow account
+    pub fn get_escrow_deployment_proof(&self, escrow_account: AccountId) -> Option<EscrowDeploymentProof> {
+        self.deployed_escrows.get(&escrow_account).map(|code_hash| EscrowDeploymentProof {
+            approved: Some(&code_hash) == self.escrow_code_hash.as_ref(),
+            account_id: escrow_account,
+            code_hash,
+            access_keys: Vec::new(),
+        })
+    }
+
+    pub fn get_order_domain(&self) -> Option<Eip712Domain> {
+        self.order_domain.clone()
+    }
+
+    pub fn get_require_signed_orders(&self) -> bool {
+        self.require_signed_orders
+    }
+
+    /// EIP-712 hash of an EVM order under the configured domain
+    pub fn hash_order(&self, order: FusionOrder) -> String {
+        let domain = self.order_domain.as_ref().expect("Order domain not set");
+        let digest = order
+            .hash(domain)
+            .unwrap_or_else(|e| env::panic_str(&e.to_string()));
+        format!("0x{}", hex::encode(digest))
+    }
+
     // === Private Methods ===
 
     fn assert_owner(&self) {
@@ -344,11 +741,170 @@ impl EscrowFactory {
         );
     }
 
+    fn assert_approved_escrow(&self, escrow_account: &AccountId) {
+        let deployed_hash = self.deployed_escrows.get(escrow_account).unwrap_or_else(|| {
+            env::panic_str(&format!("Escrow account {} was not deployed by this factory", escrow_account))
+        });
+        assert!(
+            Some(&deployed_hash) == self.escrow_code_hash.as_ref(),
+            "Escrow account {} runs unapproved code hash {}", escrow_account, deployed_hash
+        );
+        assert!(
+            self.escrow_info.get(escrow_account).is_none(),
+            "Escrow account {} is already initialized", escrow_account
+        );
+    }
+
+    /// Charge the caller for the storage added since `initial_storage` and refund the rest of the deposit
+    fn charge_storage(&self, initial_storage: StorageUsage) {
+        let cost = self.storage_cost_since(initial_storage);
+        let attached = env::attached_deposit().as_yoctonear();
+        assert!(
+            attached >= cost,
+            "Insufficient storage deposit. Required: {}, provided: {}",
+            cost, attached
+        );
+        if attached > cost {
+            Promise::new(env::predecessor_account_id()).transfer(NearToken::from_yoctonear(attached - cost));
+        }
+    }
+
+    /// Cost of the storage added since `initial_storage`
+    fn storage_cost_since(&self, initial_storage: StorageUsage) -> Balance {
+        let added = env::storage_usage().saturating_sub(initial_storage);
+        env::storage_byte_cost().as_yoctonear() * Balance::from(added)
+    }
+
+    /// Check that an escrow for one leg of an order fill can be created at `escrow_account`
+    /// with `deposit`. Applies the order's timelock preset; contract state is not changed.
+    fn check_new_escrow(
+        &self,
+        immutables: &mut EscrowImmutables,
+        escrow_type: &EscrowType,
+        signed_order: Option<&SignedOrder>,
+        deposit: Balance,
+        escrow_account: &AccountId,
+    ) -> Result<(), String> {
+        if self.legacy_escrows.is_some() {
+            return Err("Escrow migration in progress".to_string());
+        }
+
+        // Verify the maker's EVM signature before locking any funds
+        let signed_nonce = self.check_signed_order(immutables, signed_order)?;
+
+        // Validate payment
+        let required_deposit = self.calculate_required_deposit(immutables);
+        if deposit < required_deposit {
+            return Err(format!("Insufficient deposit. Required: {}, provided: {}", required_deposit, deposit));
+        }
+
+        // Check if escrow already exists for this leg of the order
+        if self.order_to_escrow.contains_key(&order_key(&immutables.order_hash, immutables.fill_index, escrow_type)) {
+            return Err(format!(
+                "Escrow already exists for order: {} fill {} ({:?})",
+                immutables.order_hash, immutables.fill_index, escrow_type
+            ));
+        }
+        if self.escrow_info.get(escrow_account).is_some() {
+            return Err(format!("Escrow account {} is already in use", escrow_account));
+        }
+
+        // Check the cross-chain order fields and deadline
+        immutables.validate().map_err(|e| e.to_string())?;
+        immutables
+            .validate_conversion(self.wnear_account.as_ref())
+            .map_err(|e| e.to_string())?;
+        self.check_supported_asset(immutables)?;
+
+        // Check that the maker has not invalidated the order
+        if self.is_order_invalidated(immutables.maker.clone(), immutables.order_hash.clone(), signed_nonce) {
+            return Err(format!("Order has been invalidated by maker: {}", immutables.order_hash));
+        }
+
+        self.check_hashlock(immutables)
+    }
+
+    /// Check that `immutables.order_hash` is the EIP-712 hash of an order signed by its maker.
+    /// Returns the nonce the maker signed in `makerTraits`, if a signed order was given.
+    fn check_signed_order(
+        &self,
+        immutables: &EscrowImmutables,
+        signed_order: Option<&SignedOrder>,
+    ) -> Result<Option<u64>, String> {
+        let Some(signed_order) = signed_order else {
+            if self.require_signed_orders {
+                return Err("Signed order required".to_string());
+            }
+            return Ok(None);
+        };
+
+        let domain = self.order_domain.as_ref().ok_or("Order domain not set")?;
+        let order_hash = signed_order.verify(domain).map_err(|e| e.to_string())?;
+
+        if normalize_hex(&order_hash) != normalize_hex(&immutables.order_hash) {
+            return Err("Order hash does not match signed order".to_string());
+        }
+        if immutables.evm_maker.as_deref().map(normalize_hex) != Some(normalize_hex(&signed_order.order.maker)) {
+            return Err("EVM maker does not match signed order".to_string());
+        }
+
+        let nonce = signed_order.order.nonce_or_epoch().map_err(|e| e.to_string())?;
+        if immutables.nonce != nonce {
+            return Err("Nonce does not match signed order".to_string());
+        }
+        Ok(Some(nonce))
+    }
+
+    /// The hashlock of this order fill of the maker must be free or already reserved for it.
+    /// The source and destination legs of a fill share it; other fills of the same order only
+    /// if the order allows partial fills. Reservations are per maker, so escrows of other makers
+    /// cannot squat a hashlock.
+    fn check_hashlock(&self, immutables: &EscrowImmutables) -> Result<(), String> {
+        let key = (immutables.maker.clone(), normalize_hex(&immutables.hashlock));
+        let Some((reserved_order_hash, fill_index)) = self.used_hashlocks.get(&key) else {
+            return Ok(());
+        };
+        if reserved_order_hash != normalize_hex(&immutables.order_hash) {
+            return Err(format!("Hashlock already used by another order: {}", reserved_order_hash));
+        }
+        if fill_index != immutables.fill_index && !immutables.allow_partial_fills {
+            return Err(format!("Hashlock already used by fill {} of this order", fill_index));
+        }
+        Ok(())
+    }
+
+    /// Reserve the hashlock checked by `check_hashlock` for this order fill
+    fn reserve_hashlock(&mut self, immutables: &EscrowImmutables) {
+        let key = (immutables.maker.clone(), normalize_hex(&immutables.hashlock));
+        if self.used_hashlocks.get(&key).is_none() {
+            self.used_hashlocks.insert(&key, &(normalize_hex(&immutables.order_hash), immutables.fill_index));
+        }
+    }
+
+    /// Free the hashlock of a failed escrow creation if it reserved it and no other leg
+    /// of its fill uses it
+    fn release_hashlock(&mut self, immutables: &EscrowImmutables) {
+        let key = (immutables.maker.clone(), normalize_hex(&immutables.hashlock));
+        let reserved_here = self
+            .used_hashlocks
+            .get(&key)
+            .is_some_and(|(_, fill_index)| fill_index == immutables.fill_index);
+        let leg_remaining = [EscrowType::Source, EscrowType::Destination]
+            .into_iter()
+            .any(|escrow_type| {
+                self.order_to_escrow
+                    .contains_key(&order_key(&immutables.order_hash, immutables.fill_index, &escrow_type))
+            });
+        if reserved_here && !leg_remaining {
+            self.used_hashlocks.remove(&key);
+        }
+    }
+
     fn calculate_required_deposit(&self, immutables: &EscrowImmutables) -> Balance {
         let mut required = self.creation_fee + MIN_STORAGE_DEPOSIT;
         
         // Add token amount for native NEAR transfers
-        if immutables.token.is_none() {
+        if immutables.asset.is_near() {
             required += immutables.amount;
         }
         
@@ -358,41 +914,972 @@ impl EscrowFactory {
         required
     }
 
-    fn generate_escrow_account_id(&self, order_hash: &str, escrow_type: &EscrowType) -> AccountId {
-        let type_prefix = match escrow_type {
-            EscrowType::Source => "src",
-            EscrowType::Destination => "dst",
+    /// Approved escrow WASM code set with `set_escrow_code`
+    fn get_escrow_wasm(&self) -> Vec<u8> {
+        self.escrow_code.get().expect("Escrow code not set")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+    use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_sdk::{PromiseError, PromiseOrValue};
+    use shared::{
+        BitcoinHashlock, BitcoinTimelockPreset, CryptoUtils, EscrowAsset, NearConversion, PayoutCall,
+        TimelockClock, Timelocks, NEAR_CHAIN_ID,
+    };
+
+    fn set_context(predecessor: AccountId, deposit: NearToken) {
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id(predecessor)
+            .attached_deposit(deposit)
+            .build());
+    }
+
+    fn escrow_account(order_hash: &str, fill_index: u64, escrow_type: EscrowType) -> AccountId {
+        escrow_account_id(&"factory.near".parse().unwrap(), order_hash, fill_index, &escrow_type)
+    }
+
+    fn set_code(factory: &mut EscrowFactory, code: &[u8]) {
+        let mut context = VMContextBuilder::new()
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id(accounts(0))
+            .build();
+        context.input = code.to_vec();
+        testing_env!(context);
+        factory.set_escrow_code();
+    }
+
+    fn setup_factory() -> EscrowFactory {
+        set_context(accounts(0), NearToken::from_near(0));
+        let mut factory = EscrowFactory::new(accounts(0), U128(0), None, Some(accounts(3)));
+        set_code(&mut factory, b"escrow wasm");
+        factory
+    }
+
+    fn test_immutables(order_hash: &str) -> EscrowImmutables {
+        EscrowImmutables {
+            order_hash: order_hash.to_string(),
+            hashlock: CryptoUtils::create_hashlock("test_secret_123"),
+            maker: accounts(1),
+            taker: accounts(2),
+            asset: EscrowAsset::Near,
+            amount: 1_000_000_000_000_000_000_000_000, // 1 NEAR
+            safety_deposit: 100_000_000_000_000_000_000_000, // 0.1 NEAR
+            timelocks: Timelocks::new(3600, 7200, 86400),
+            nonce: 0,
+            src_chain_id: 1,
+            dst_chain_id: NEAR_CHAIN_ID,
+            counterpart_token: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            counterpart_amount: "400000000000000000".to_string(),
+            evm_maker: Some("0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string()),
+            evm_receiver: None,
+            deadline: 3600,
+            receiver: None,
+            fill_index: 0,
+            allow_partial_fills: false,
+            payout_call: None,
+            payout_conversion: None,
+            bitcoin_hashlock: None,
+            bitcoin_timelock_preset: None,
+        }
+    }
+
+    #[test]
+    fn test_invalidate_order() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(1), NearToken::from_millinear(10));
+        factory.invalidate_order("0xABCDEF0123".to_string());
+
+        assert!(factory.is_order_invalidated(accounts(1), "0xabcdef0123".to_string(), None));
+        // Invalidation is scoped to the maker that requested it
+        assert!(!factory.is_order_invalidated(accounts(2), "0xabcdef0123".to_string(), None));
+    }
+
+    #[test]
+    fn test_increase_nonce() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(1), NearToken::from_millinear(10));
+        assert_eq!(factory.increase_nonce(), 1);
+
+        assert!(factory.is_order_invalidated(accounts(1), "0xabcdef0123".to_string(), Some(0)));
+        assert!(!factory.is_order_invalidated(accounts(1), "0xabcdef0123".to_string(), Some(1)));
+        // A nonce ahead of the maker's cannot skip past future bumps
+        assert!(factory.is_order_invalidated(accounts(1), "0xabcdef0123".to_string(), Some(u64::MAX)));
+        // Unsigned orders carry no nonce the maker committed to
+        assert!(!factory.is_order_invalidated(accounts(1), "0xabcdef0123".to_string(), None));
+    }
+
+    /// Order signed by the EVM maker of `test_immutables`, with nonce 0 in its `makerTraits`
+    fn signed_test_order() -> (EscrowImmutables, SignedOrder) {
+        let immutables = test_immutables("0x7b8bf6236bd6d7e8745abf60f0cef1d7f5b397abc445ee01e52146a6f54eae17");
+        let order = FusionOrder {
+            salt: "42".to_string(),
+            maker: "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string(),
+            receiver: "0x0000000000000000000000000000000000000000".to_string(),
+            maker_asset: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            taker_asset: "0xdac17f958d2ee523a2206206994597c13d831ec7".to_string(),
+            making_amount: "1000000000000000000".to_string(),
+            taking_amount: "0xb2d05e00".to_string(),
+            maker_traits: "0".to_string(),
         };
-        
-        let account_str = format!(
-            "{}-{}.{}",
-            type_prefix,
-            &order_hash[..8], // First 8 chars of order hash
-            env::current_account_id()
+        let signature = "0x335a95348caf4cfc5a992222b7efbd74065a4ad0c5cd26ffb6b719eb11b110f02c54a1c8b9c589c8722cf6d535b72a9c5b5207de429b68ef82e963812100655e1c";
+        (immutables, SignedOrder { order, signature: signature.to_string() })
+    }
+
+    fn set_test_order_domain(factory: &mut EscrowFactory) {
+        set_context(accounts(0), NearToken::from_near(0));
+        factory.set_order_domain(Some(Eip712Domain::oneinch(
+            1,
+            "0x111111125421ca6dc452d289314280a0f8842a65".to_string(),
+        )));
+    }
+
+    #[test]
+    fn test_signed_order_under_current_nonce() {
+        let mut factory = setup_factory();
+        set_test_order_domain(&mut factory);
+        let (immutables, signed_order) = signed_test_order();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables.clone(), Some(signed_order));
+        assert!(factory.get_escrow_for_order(immutables.order_hash, None).destination.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Order has been invalidated by maker")]
+    fn test_increase_nonce_invalidates_signed_order() {
+        let mut factory = setup_factory();
+        set_test_order_domain(&mut factory);
+        set_context(accounts(1), NearToken::from_millinear(10));
+        factory.increase_nonce();
+
+        // The order was signed under nonce 0
+        let (immutables, signed_order) = signed_test_order();
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables, Some(signed_order));
+    }
+
+    #[test]
+    #[should_panic(expected = "Nonce does not match signed order")]
+    fn test_nonce_must_match_signed_order() {
+        let mut factory = setup_factory();
+        set_test_order_domain(&mut factory);
+        set_context(accounts(1), NearToken::from_millinear(10));
+        factory.increase_nonce();
+
+        // A resolver cannot revive the order by claiming the maker's current nonce
+        let (mut immutables, signed_order) = signed_test_order();
+        immutables.nonce = 1;
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables, Some(signed_order));
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage deposit")]
+    fn test_invalidate_order_requires_storage_deposit() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(1), NearToken::from_near(0));
+        factory.invalidate_order("0xabcdef0123".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Order has been invalidated by maker")]
+    fn test_invalidated_order_cannot_be_filled() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(1), NearToken::from_millinear(10));
+        factory.invalidate_order("0xabcdef0123".to_string());
+
+        set_context(accounts(2), NearToken::from_near(5));
+        factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Order deadline has passed")]
+    fn test_expired_order_cannot_be_filled() {
+        let mut factory = setup_factory();
+
+        let mut immutables = test_immutables("0xabcdef0123");
+        immutables.deadline = 0;
+
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(NearToken::from_near(5))
+            .block_timestamp(60 * 1_000_000_000)
+            .build());
+        factory.create_dst_escrow(immutables, None);
+    }
+
+    #[test]
+    fn test_source_and_destination_for_same_order() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(test_immutables("0xabcdef0123"), None);
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+
+        let escrows = factory.get_escrow_for_order("0xabcdef0123".to_string(), None);
+        assert_eq!(escrows.source, Some(escrow_account("0xabcdef0123", 0, EscrowType::Source)));
+        assert_eq!(escrows.destination, Some(escrow_account("0xabcdef0123", 0, EscrowType::Destination)));
+        assert_eq!(
+            factory.get_escrow_for_order_leg("0xabcdef0123".to_string(), EscrowType::Destination, None),
+            escrows.destination
         );
-        
-        account_str.parse().unwrap()
     }
 
-    /// Placeholder for getting escrow WASM code
-    /// In a real implementation, you would:
-    /// 1. Store the WASM as a global contract
-    /// 2. Load from an external source
-    /// 3. Use a pre-deployed template pattern
-    fn get_escrow_wasm(&self) -> Vec<u8> {
-        // For now, return empty - this will need to be implemented based on your deployment strategy
-        // You could read from a file, fetch from another contract, or use global contracts
-        Vec::new()
+    #[test]
+    #[should_panic(expected = "Escrow already exists for order")]
+    fn test_order_hash_case_does_not_bypass_duplicate_check() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+        assert_eq!(
+            factory.get_escrow_for_order("0xABCDEF0123".to_string(), None).destination,
+            Some(escrow_account("0xabcdef0123", 0, EscrowType::Destination))
+        );
+        let _ = factory.create_dst_escrow(test_immutables("0xABCDEF0123"), None);
     }
-}
 
-// External contract interface for callbacks
-#[near_sdk::ext_contract(ext_self)]
-trait ExtSelf {
-    fn on_escrow_created(
-        &mut self,
-        escrow_account_id: AccountId,
-        order_hash: String,
-        fee: Balance,
-    ) -> bool;
-}
\ No newline at end of file
+    #[test]
+    fn test_revealed_secret_registry() {
+        let mut factory = setup_factory();
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(test_immutables("0xabcdef0123"), None);
+        assert_eq!(factory.get_revealed_secret("0xabcdef0123".to_string(), 0), None);
+
+        set_context(escrow_account("0xabcdef0123", 0, EscrowType::Source), NearToken::from_near(0));
+        factory.report_secret("test_secret_123".to_string());
+
+        assert_eq!(
+            factory.get_revealed_secret("0xabcdef0123".to_string(), 0),
+            Some("test_secret_123".to_string())
+        );
+        assert!(near_sdk::test_utils::get_logs()[0].starts_with("EVENT_JSON:"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Hashlock already used by another order")]
+    fn test_hashlock_reuse_across_orders_is_rejected() {
+        let mut factory = setup_factory();
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(test_immutables("0xabcdef0123"), None);
+        assert!(factory.is_hashlock_used(accounts(1), CryptoUtils::create_hashlock("test_secret_123")));
+
+        // Same secret, different order
+        let _ = factory.create_src_escrow(test_immutables("0x99999999aa"), None);
+    }
+
+    #[test]
+    fn test_hashlock_shared_by_partial_fills() {
+        let mut factory = setup_factory();
+        let mut first_fill = test_immutables("0xabcdef0123");
+        first_fill.allow_partial_fills = true;
+        let mut second_fill = first_fill.clone();
+        second_fill.fill_index = 1;
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(first_fill.clone(), None);
+        let _ = factory.create_src_escrow(second_fill, None);
+        assert!(factory.is_hashlock_used(accounts(1), first_fill.hashlock));
+
+        // Each fill gets its own escrow account
+        assert_eq!(
+            factory.get_escrow_for_order("0xabcdef0123".to_string(), None).source,
+            Some(escrow_account("0xabcdef0123", 0, EscrowType::Source))
+        );
+        assert_eq!(
+            factory.get_escrow_for_order("0xabcdef0123".to_string(), Some(1)).source,
+            Some(escrow_account("0xabcdef0123", 1, EscrowType::Source))
+        );
+    }
+
+    #[test]
+    fn test_hashlock_reserved_per_maker() {
+        let mut factory = setup_factory();
+        let mut squatter = test_immutables("0x99999999aa");
+        squatter.maker = accounts(3);
+        set_context(accounts(3), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(squatter, None);
+
+        // Another maker's order with the same hashlock is not blocked, and hex case does not matter
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_src_escrow(test_immutables("0xabcdef0123"), None);
+        let _ = factory.create_dst_escrow(test_immutables("0xABCDEF0123"), None);
+        assert!(factory.is_hashlock_used(accounts(1), CryptoUtils::create_hashlock("test_secret_123")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid immutables")]
+    fn test_conversion_needs_configured_wnear() {
+        let mut factory = setup_factory();
+        let mut immutables = test_immutables("0xabcdef0123");
+        immutables.payout_conversion = Some(NearConversion::Wrap);
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables, None);
+    }
+
+    #[test]
+    fn test_conversion_with_configured_wnear() {
+        let mut factory = setup_factory();
+        factory.set_wnear_account(Some("wrap.near".parse().unwrap()));
+        let mut immutables = test_immutables("0xabcdef0123");
+        immutables.payout_conversion = Some(NearConversion::Wrap);
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables, None);
+        assert!(factory.get_escrow_for_order("0xabcdef0123".to_string(), None).destination.is_some());
+    }
+
+    fn usdc_config() -> TokenConfig {
+        TokenConfig {
+            min_amount: U128(1_000_000),
+            max_amount: U128(1_000_000_000_000),
+            min_safety_deposit: U128(100_000_000_000_000_000_000_000),
+            decimals: Some(6),
+            symbol: Some("USDC".to_string()),
+        }
+    }
+
+    fn usdc_immutables(amount: Balance) -> EscrowImmutables {
+        let mut immutables = test_immutables("0xabcdef0123");
+        immutables.asset = EscrowAsset::Ft { contract_id: "usdc.near".parse().unwrap() };
+        immutables.amount = amount;
+        immutables
+    }
+
+    #[test]
+    fn test_registered_token_escrow() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        // Decimals and symbol were given, so no metadata is fetched
+        assert!(near_sdk::test_utils::get_created_receipts().is_empty());
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(usdc_immutables(5_000_000), None);
+        assert!(factory.get_escrow_for_order("0xabcdef0123".to_string(), None).destination.is_some());
+
+        let tokens = factory.get_supported_tokens(None, None);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].config.symbol.as_deref(), Some("USDC"));
+    }
+
+    #[test]
+    fn test_supported_tokens_are_paginated() {
+        let mut factory = setup_factory();
+        for token in ["usdc.near", "usdt.near", "dai.near"] {
+            factory.register_token(token.parse().unwrap(), usdc_config());
+        }
+
+        let page: Vec<String> = factory
+            .get_supported_tokens(Some(1), Some(1))
+            .into_iter()
+            .map(|token| token.token_id.to_string())
+            .collect();
+        assert_eq!(page, vec!["usdt.near".to_string()]);
+        assert_eq!(factory.get_supported_tokens(Some(1), None).len(), 2);
+        assert!(factory.get_supported_tokens(Some(3), Some(10)).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Minimum amount must not exceed maximum amount")]
+    fn test_register_token_rejects_inverted_limits() {
+        let mut factory = setup_factory();
+        let mut config = usdc_config();
+        config.min_amount = U128(config.max_amount.0 + 1);
+        factory.register_token("usdc.near".parse().unwrap(), config);
+    }
+
+    #[test]
+    #[should_panic(expected = "Minimum safety deposit must be positive")]
+    fn test_register_token_rejects_zero_safety_deposit() {
+        let mut factory = setup_factory();
+        let mut config = usdc_config();
+        config.min_safety_deposit = U128(0);
+        factory.register_token("usdc.near".parse().unwrap(), config);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token not supported: usdc.near")]
+    fn test_unregistered_token_is_rejected() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(usdc_immutables(5_000_000), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount out of range for usdc.near")]
+    fn test_token_amount_limits() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(usdc_immutables(999_999), None);
+    }
+
+    #[test]
+    fn test_token_metadata_is_cached() {
+        let mut factory = setup_factory();
+        let token: AccountId = "usdc.near".parse().unwrap();
+        factory.register_token(token.clone(), TokenConfig { decimals: None, symbol: None, ..usdc_config() });
+        assert_eq!(near_sdk::test_utils::get_created_receipts()[0].receiver_id, token);
+
+        set_context("factory.near".parse().unwrap(), NearToken::from_near(0));
+        let metadata = FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: "USD Coin".to_string(),
+            symbol: "USDC".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals: 6,
+        };
+        assert!(factory.on_token_metadata(token.clone(), Ok(metadata)));
+
+        let config = factory.get_token_config(token).unwrap();
+        assert_eq!(config.decimals, Some(6));
+        assert_eq!(config.symbol.as_deref(), Some("USDC"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only escrows created by this factory can report secrets")]
+    fn test_unknown_account_cannot_report_secret() {
+        let mut factory = setup_factory();
+        set_context(accounts(4), NearToken::from_near(0));
+        factory.report_secret("test_secret_123".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Escrow already exists for order")]
+    fn test_duplicate_leg_is_rejected() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+    }
+
+    #[test]
+    fn test_create_escrows_batch() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(10));
+        let _ = factory.create_escrows_batch(vec![
+            (EscrowType::Source, test_immutables("0xabcdef0123"), None),
+            (EscrowType::Destination, test_immutables("0xabcdef0123"), None),
+        ]);
+
+        let escrows = factory.get_escrow_for_order("0xabcdef0123".to_string(), None);
+        assert!(escrows.source.is_some() && escrows.destination.is_some());
+        // The excess deposit is refunded to the resolver
+        assert!(near_sdk::test_utils::get_created_receipts()
+            .iter()
+            .any(|receipt| receipt.receiver_id == accounts(2)));
+    }
+
+    #[test]
+    fn test_escrows_batch_skips_invalid_items() {
+        let mut factory = setup_factory();
+
+        // The second item repeats the first leg
+        set_context(accounts(2), NearToken::from_near(10));
+        let result = factory.create_escrows_batch(vec![
+            (EscrowType::Source, test_immutables("0xabcdef0123"), None),
+            (EscrowType::Source, test_immutables("0xabcdef0123"), None),
+        ]);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(factory.get_escrow_for_order("0xabcdef0123".to_string(), None).source.is_some());
+
+        // The skipped item's deposit is refunded with the excess
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        let refund = receipts.iter().find(|receipt| receipt.receiver_id == accounts(2)).unwrap();
+        assert!(matches!(
+            refund.actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit, .. } if deposit == NearToken::from_millinear(5_900)
+        ));
+
+        let account = escrow_account("0xabcdef0123", 0, EscrowType::Source);
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id("factory.near".parse().unwrap())
+                .predecessor_account_id("factory.near".parse().unwrap())
+                .build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(b"true".to_vec())],
+        );
+        assert_eq!(
+            factory.on_batch_created(vec![(account.clone(), true), (account.clone(), false)]),
+            vec![(account.clone(), true), (account, false)]
+        );
+    }
+
+    #[test]
+    fn test_escrows_batch_without_valid_items() {
+        let mut factory = setup_factory();
+        let mut expired = test_immutables("0xabcdef0123");
+        expired.deadline = 0;
+
+        testing_env!(VMContextBuilder::new()
+            .current_account_id("factory.near".parse().unwrap())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(NearToken::from_near(5))
+            .block_timestamp(60 * 1_000_000_000)
+            .build());
+        let result = factory.create_escrows_batch(vec![(EscrowType::Destination, expired, None)]);
+        assert!(matches!(result, PromiseOrValue::Value(ref items) if items.len() == 1 && !items[0].1));
+        assert!(factory.get_escrow_for_order("0xabcdef0123".to_string(), None).destination.is_none());
+        assert!(!factory.is_hashlock_used(accounts(1), CryptoUtils::create_hashlock("test_secret_123")));
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient deposit")]
+    fn test_escrows_batch_requires_full_deposit() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(2));
+        let _ = factory.create_escrows_batch(vec![
+            (EscrowType::Source, test_immutables("0xabcdef0123"), None),
+            (EscrowType::Destination, test_immutables("0xabcdef0123"), None),
+        ]);
+    }
+
+    /// Prepaid gas of the calls to `method` in the created receipts
+    fn call_gas(method: &str) -> Vec<Gas> {
+        near_sdk::test_utils::get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| receipt.actions)
+            .filter_map(|action| match action {
+                near_sdk::mock::MockAction::FunctionCallWeight { method_name, prepaid_gas, .. }
+                    if method_name == method.as_bytes() => Some(prepaid_gas),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_withdraw_sizes_gas_per_escrow() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(2), NearToken::from_near(10));
+        let _ = factory.create_src_escrow(test_immutables("0x11111111aa"), None);
+        let mut token_escrow = usdc_immutables(5_000_000);
+        token_escrow.hashlock = CryptoUtils::create_hashlock("other_secret");
+        let _ = factory.create_dst_escrow(token_escrow, None);
+
+        set_context(accounts(2), NearToken::from_near(0));
+        let _ = factory.batch_withdraw(vec![
+            (escrow_account("0x11111111aa", 0, EscrowType::Source), "test_secret_123".to_string()),
+            (escrow_account("0xabcdef0123", 0, EscrowType::Destination), "other_secret".to_string()),
+        ]);
+        assert_eq!(call_gas("withdraw_for"), vec![Gas::from_tgas(25), Gas::from_tgas(80)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch needs 510 TGas")]
+    fn test_batch_withdraw_rejects_batch_over_gas() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(2), NearToken::from_near(10));
+        let mut escrows = Vec::new();
+        for (order_hash, secret) in [("0x11111111aa", "first_secret"), ("0x22222222aa", "second_secret")] {
+            let mut immutables = usdc_immutables(5_000_000);
+            immutables.order_hash = order_hash.to_string();
+            immutables.hashlock = CryptoUtils::create_hashlock(secret);
+            immutables.payout_call = Some(PayoutCall { receiver_id: "dex.near".parse().unwrap(), msg: String::new() });
+            let _ = factory.create_src_escrow(immutables, None);
+            escrows.push((escrow_account(order_hash, 0, EscrowType::Source), secret.to_string()));
+        }
+
+        set_context(accounts(2), NearToken::from_near(0));
+        let _ = factory.batch_withdraw(escrows);
+    }
+
+    #[test]
+    fn test_bitcoin_preset_is_applied_on_creation() {
+        let mut factory = setup_factory();
+        let mut immutables = test_immutables("0xabcdef0123");
+        immutables.bitcoin_hashlock = Some(BitcoinHashlock::Sha256);
+        immutables.bitcoin_timelock_preset = Some(BitcoinTimelockPreset::Standard);
+        immutables.timelocks.clock = TimelockClock::BlockHeight;
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(immutables, None);
+
+        let escrow = factory.get_escrow_for_order("0xabcdef0123".to_string(), None).destination.unwrap();
+        let timelocks = factory.get_escrow_info(escrow).unwrap().immutables.timelocks;
+        // 12h and 24h of 600ms NEAR blocks
+        assert_eq!(timelocks.withdrawal_period, 72_000);
+        assert_eq!(timelocks.cancellation_period, 144_000);
+    }
+
+    /// Write a factory of the original layout holding one source escrow per order hash
+    fn write_legacy_factory(order_hashes: &[&str]) {
+        use crate::migration::{LegacyEscrowFactory, LegacyEscrowImmutables, LegacyEscrowInfo, LegacyTimelocks};
+
+        let mut legacy = LegacyEscrowFactory {
+            owner: accounts(0),
+            order_to_escrow: LookupMap::new(b"o"),
+            escrow_info: UnorderedMap::new(b"e"),
+            creation_fee: 7,
+            treasury: Some(accounts(3)),
+            escrow_template: None,
+        };
+        for order_hash in order_hashes {
+            let escrow: AccountId = format!("src-{}.factory.near", order_hash).parse().unwrap();
+            legacy.order_to_escrow.insert(&order_hash.to_string(), &escrow);
+            legacy.escrow_info.insert(&escrow, &LegacyEscrowInfo {
+                escrow_type: EscrowType::Source,
+                immutables: LegacyEscrowImmutables {
+                    order_hash: order_hash.to_string(),
+                    hashlock: CryptoUtils::create_hashlock(order_hash),
+                    maker: accounts(1),
+                    taker: accounts(2),
+                    token: Some("usdc.near".parse().unwrap()),
+                    amount: 5_000_000,
+                    safety_deposit: 100,
+                    timelocks: LegacyTimelocks {
+                        deployed_at: 1,
+                        withdrawal_period: 3600,
+                        cancellation_period: 7200,
+                        rescue_delay: 86400,
+                    },
+                },
+                creator: accounts(2),
+                created_at: 1,
+            });
+        }
+        env::state_write(&legacy);
+    }
+
+    #[test]
+    fn test_migrate_from_original_layout() {
+        set_context("factory.near".parse().unwrap(), NearToken::from_near(0));
+        write_legacy_factory(&["0xabcdef0123", "0x0123abcdef"]);
+
+        let mut factory = EscrowFactory::migrate();
+        assert_eq!(factory.get_creation_fee(), U128(7));
+        assert_eq!(factory.get_escrows_to_migrate(), 2);
+
+        assert_eq!(factory.migrate_escrows(1), 1);
+        assert_eq!(factory.migrate_escrows(1), 0);
+        assert_eq!(factory.get_escrows_to_migrate(), 0);
+
+        for order_hash in ["0xabcdef0123", "0x0123abcdef"] {
+            let escrow: AccountId = format!("src-{}.factory.near", order_hash).parse().unwrap();
+            assert_eq!(factory.get_escrow_for_order(order_hash.to_string(), None).source, Some(escrow.clone()));
+            let info = factory.get_escrow_info(escrow).unwrap();
+            assert_eq!(info.immutables.asset, EscrowAsset::Ft { contract_id: "usdc.near".parse().unwrap() });
+            assert_eq!(info.immutables.timelocks.withdrawal_period, 3600);
+            assert!(factory.is_hashlock_used(accounts(1), CryptoUtils::create_hashlock(order_hash)));
+
+            // The original order map entry is removed
+            assert!(!env::storage_has_key(&[b"o".as_slice(), &borsh::to_vec(order_hash).unwrap()].concat()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Escrow migration in progress")]
+    fn test_create_escrow_blocked_during_migration() {
+        set_context("factory.near".parse().unwrap(), NearToken::from_near(0));
+        write_legacy_factory(&["0xabcdef0123", "0x0123abcdef"]);
+
+        let mut factory = EscrowFactory::migrate();
+        set_code(&mut factory, b"escrow wasm");
+        assert_eq!(factory.migrate_escrows(1), 1);
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(test_immutables("0xfedcba9876"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown escrow")]
+    fn test_batch_cancel_rejects_unknown_escrow() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(0));
+        let _ = factory.batch_cancel(vec![escrow_account("0xabcdef0123", 0, EscrowType::Destination)]);
+    }
+
+    #[test]
+    fn test_initialize_predeployed_escrow() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(3));
+        let _ = factory.deploy_escrow_account("pool-1".to_string());
+        let escrow_account: AccountId = "pre-pool-1.factory.near".parse().unwrap();
+        assert!(factory.is_approved_escrow(escrow_account.clone()));
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.initialize_escrow(
+            escrow_account.clone(),
+            test_immutables("0xabcdef0123"),
+            EscrowType::Destination,
+            None,
+        );
+        assert_eq!(
+            factory.get_escrow_for_order("0xabcdef0123".to_string(), None).destination,
+            Some(escrow_account)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is already in use")]
+    fn test_escrow_account_in_use_is_not_overwritten() {
+        let mut factory = setup_factory();
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+
+        // Simulate another order whose escrow name collides with the live escrow
+        let live_info = factory
+            .get_escrow_info(escrow_account("0xabcdef0123", 0, EscrowType::Destination))
+            .unwrap();
+        let mut other_order = test_immutables("0x99999999aa");
+        other_order.hashlock = CryptoUtils::create_hashlock("other_secret");
+        factory.escrow_info.insert(&escrow_account("0x99999999aa", 0, EscrowType::Destination), &live_info);
+        let _ = factory.create_dst_escrow(other_order, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Escrow account name must not contain '.'")]
+    fn test_predeployed_name_must_be_direct_sub_account() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(3));
+        let _ = factory.deploy_escrow_account("pool.1".to_string());
+    }
+
+    #[test]
+    fn test_failed_predeployment_is_refunded() {
+        let mut factory = setup_factory();
+        set_context(accounts(2), NearToken::from_near(3));
+        let _ = factory.deploy_escrow_account("pool-1".to_string());
+        let escrow_account: AccountId = "pre-pool-1.factory.near".parse().unwrap();
+
+        testing_env!(
+            VMContextBuilder::new()
+                .current_account_id("factory.near".parse().unwrap())
+                .predecessor_account_id("factory.near".parse().unwrap())
+                .build(),
+            near_sdk::test_vm_config(),
+            near_sdk::RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let deposit = NearToken::from_near(3).as_yoctonear();
+        assert!(!factory.on_escrow_deployed(escrow_account.clone(), accounts(2), U128(deposit)));
+        assert!(!factory.is_approved_escrow(escrow_account));
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts[0].receiver_id, accounts(2));
+        assert!(matches!(
+            receipts[0].actions[0],
+            near_sdk::mock::MockAction::Transfer { deposit: refund, .. } if refund.as_yoctonear() == deposit
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "was not deployed by this factory")]
+    fn test_initialize_rejects_foreign_account() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.initialize_escrow(accounts(4), test_immutables("0xabcdef0123"), EscrowType::Destination, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "runs unapproved code hash")]
+    fn test_initialize_rejects_outdated_code() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(3));
+        let _ = factory.deploy_escrow_account("pool-1".to_string());
+        set_code(&mut factory, b"escrow wasm v2");
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.initialize_escrow(
+            "pre-pool-1.factory.near".parse().unwrap(),
+            test_immutables("0xabcdef0123"),
+            EscrowType::Destination,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_escrow_accounts_are_keyless() {
+        let mut factory = setup_factory();
+
+        set_context(accounts(2), NearToken::from_near(5));
+        let _ = factory.create_dst_escrow(test_immutables("0xabcdef0123"), None);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        let escrow_account: AccountId = escrow_account("0xabcdef0123", 0, EscrowType::Destination);
+        let create = receipts.iter().find(|r| r.receiver_id == escrow_account).unwrap();
+        assert!(!create.actions.iter().any(|a| matches!(
+            a,
+            near_sdk::mock::MockAction::AddKeyWithFullAccess { .. }
+                | near_sdk::mock::MockAction::AddKeyWithFunctionCall { .. }
+        )));
+
+        let proof = factory.get_escrow_deployment_proof(escrow_account).unwrap();
+        assert_eq!(Some(proof.code_hash), factory.get_escrow_code_hash());
+        assert!(proof.approved);
+        assert!(proof.access_keys.is_empty());
+    }
+
+    fn swap_terms() -> NearSwapTerms {
+        NearSwapTerms {
+            taker: Some(accounts(2)),
+            taker_token: Some("usdc.near".parse().unwrap()),
+            taker_amount: U128(3_000_000),
+            deadline: 1_000,
+        }
+    }
+
+    #[test]
+    fn test_open_and_fill_near_swap() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_near(1));
+        let swap_id = factory.open_near_swap(swap_terms());
+        // The swap record's storage is paid out of the deposit
+        let maker_amount = factory.get_near_swap(swap_id).unwrap().maker_amount;
+        assert!(maker_amount > 0 && maker_amount < NearToken::from_near(1).as_yoctonear());
+
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let msg = format!("{{\"FillSwap\":{{\"swap_id\":{}}}}}", swap_id);
+        let refund = factory.ft_on_transfer(accounts(2), U128(3_500_000), msg);
+        assert!(matches!(refund, PromiseOrValue::Value(U128(500_000))));
+        assert!(factory.get_near_swap(swap_id).is_none());
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == accounts(2)));
+        assert!(receipts.iter().any(|receipt| receipt.receiver_id == "usdc.near".parse::<AccountId>().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposit must exceed the swap storage cost")]
+    fn test_near_swap_requires_storage_deposit() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_yoctonear(1));
+        factory.open_near_swap(swap_terms());
+    }
+
+    #[test]
+    fn test_failed_swap_payout_can_be_claimed() {
+        let mut factory = setup_factory();
+        let usdc: AccountId = "usdc.near".parse().unwrap();
+
+        set_context("factory.near".parse().unwrap(), NearToken::from_near(0));
+        assert!(!factory.on_swap_payout(Some(usdc.clone()), accounts(1), U128(3_000_000), Err(PromiseError::Failed)));
+        assert_eq!(factory.get_unclaimed_swap_payout(accounts(1), Some(usdc.clone())).0, 3_000_000);
+
+        set_context(accounts(1), NearToken::from_near(0));
+        let _ = factory.claim_swap_payout(Some(usdc.clone()));
+        assert_eq!(factory.get_unclaimed_swap_payout(accounts(1), Some(usdc.clone())).0, 0);
+        assert_eq!(near_sdk::test_utils::get_created_receipts()[0].receiver_id, usdc);
+    }
+
+    #[test]
+    #[should_panic(expected = "can fill this swap")]
+    fn test_near_swap_rejects_other_taker() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_near(1));
+        let swap_id = factory.open_near_swap(swap_terms());
+
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let msg = format!("{{\"FillSwap\":{{\"swap_id\":{}}}}}", swap_id);
+        let _ = factory.ft_on_transfer(accounts(4), U128(3_000_000), msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "Token not supported: usdc.near")]
+    fn test_near_swap_must_ask_for_registered_token() {
+        let mut factory = setup_factory();
+        set_context(accounts(1), NearToken::from_near(1));
+        factory.open_near_swap(swap_terms());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token not supported: usdc.near")]
+    fn test_near_swap_rejects_removed_token() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_near(1));
+        let swap_id = factory.open_near_swap(swap_terms());
+
+        set_context(accounts(0), NearToken::from_near(0));
+        factory.remove_token("usdc.near".parse().unwrap());
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let msg = format!("{{\"FillSwap\":{{\"swap_id\":{}}}}}", swap_id);
+        let _ = factory.ft_on_transfer(accounts(2), U128(3_000_000), msg);
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount out of range for usdc.near")]
+    fn test_token_funded_swap_respects_limits() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let _ = factory.ft_on_transfer(accounts(1), U128(999_999), TOKEN_SWAP_MSG.to_string());
+    }
+
+    const TOKEN_SWAP_MSG: &str = r#"{"OpenSwap":{"taker":null,"taker_token":null,"taker_amount":"1000","deadline":1000}}"#;
+
+    #[test]
+    fn test_token_funded_swap_uses_storage_deposit() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_millinear(100));
+        let deposited = factory.deposit_swap_storage().0;
+        assert!(deposited > 0 && deposited < NearToken::from_millinear(100).as_yoctonear());
+
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let _ = factory.ft_on_transfer(accounts(1), U128(5_000_000), TOKEN_SWAP_MSG.to_string());
+        let left = factory.get_swap_storage_deposit(accounts(1)).0;
+        assert!(left > 0 && left < deposited);
+
+        set_context(accounts(1), NearToken::from_near(0));
+        let _ = factory.withdraw_swap_storage();
+        assert_eq!(factory.get_swap_storage_deposit(accounts(1)).0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient swap storage deposit")]
+    fn test_token_funded_swap_requires_storage_deposit() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+
+        set_context("usdc.near".parse().unwrap(), NearToken::from_near(0));
+        let _ = factory.ft_on_transfer(accounts(1), U128(5_000_000), TOKEN_SWAP_MSG.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only maker can cancel the swap")]
+    fn test_only_maker_cancels_near_swap() {
+        let mut factory = setup_factory();
+        factory.register_token("usdc.near".parse().unwrap(), usdc_config());
+        set_context(accounts(1), NearToken::from_near(1));
+        let swap_id = factory.open_near_swap(swap_terms());
+
+        set_context(accounts(2), NearToken::from_near(0));
+        factory.cancel_near_swap(swap_id);
+    }
+}
@@ -0,0 +1,188 @@
+//! Upgrade of a factory deployed with the original state layout.
+//!
+//! The original factory kept only its owner, fee settings and two maps: escrow
+//! accounts by order hash under `b"o"`, and escrow info under `b"e"` with the
+//! original immutables. `migrate` switches to the current layout and keeps the old
+//! maps aside; `migrate_escrows` then moves escrows in batches into the maps keyed
+//! by order hash, fill index and escrow type, reserving their hashlocks and removing
+//! them from the old maps. Escrow entry points are blocked until every escrow moved.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::json_types::U128;
+use near_sdk::{env, log, near_bindgen, AccountId, Timestamp};
+
+use shared::eip712::normalize_hex;
+use shared::{Balance, EscrowAsset, EscrowImmutables, EscrowType, TimelockClock, Timelocks};
+
+use crate::{order_key, EscrowFactory, EscrowFactoryExt, EscrowInfo};
+
+/// Timelocks as stored by the original factory
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyTimelocks {
+    pub deployed_at: Timestamp,
+    pub withdrawal_period: u64,
+    pub cancellation_period: u64,
+    pub rescue_delay: u64,
+}
+
+/// Immutables as stored by the original factory
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyEscrowImmutables {
+    pub order_hash: String,
+    pub hashlock: String,
+    pub maker: AccountId,
+    pub taker: AccountId,
+    pub token: Option<AccountId>,
+    pub amount: Balance,
+    pub safety_deposit: Balance,
+    pub timelocks: LegacyTimelocks,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyEscrowInfo {
+    pub escrow_type: EscrowType,
+    pub immutables: LegacyEscrowImmutables,
+    pub creator: AccountId,
+    pub created_at: u64,
+}
+
+/// Maps of the original factory still holding escrows to migrate
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyEscrows {
+    pub order_to_escrow: LookupMap<String, AccountId>,
+    pub escrow_info: UnorderedMap<AccountId, LegacyEscrowInfo>,
+}
+
+/// State layout of the original factory
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyEscrowFactory {
+    pub owner: AccountId,
+    pub order_to_escrow: LookupMap<String, AccountId>,
+    pub escrow_info: UnorderedMap<AccountId, LegacyEscrowInfo>,
+    pub creation_fee: Balance,
+    pub treasury: Option<AccountId>,
+    pub escrow_template: Option<AccountId>,
+}
+
+impl From<LegacyEscrowImmutables> for EscrowImmutables {
+    /// Order fields the original factory did not record are left empty
+    fn from(legacy: LegacyEscrowImmutables) -> Self {
+        Self {
+            order_hash: legacy.order_hash,
+            hashlock: legacy.hashlock,
+            maker: legacy.maker,
+            taker: legacy.taker,
+            asset: legacy
+                .token
+                .map_or(EscrowAsset::Near, |contract_id| EscrowAsset::Ft { contract_id }),
+            amount: legacy.amount,
+            safety_deposit: legacy.safety_deposit,
+            timelocks: Timelocks {
+                deployed_at: legacy.timelocks.deployed_at,
+                deployed_at_height: 0,
+                clock: TimelockClock::Timestamp,
+                withdrawal_period: legacy.timelocks.withdrawal_period,
+                cancellation_period: legacy.timelocks.cancellation_period,
+                rescue_delay: legacy.timelocks.rescue_delay,
+            },
+            nonce: 0,
+            src_chain_id: 0,
+            dst_chain_id: 0,
+            counterpart_token: String::new(),
+            counterpart_amount: String::new(),
+            evm_maker: None,
+            evm_receiver: None,
+            deadline: 0,
+            receiver: None,
+            fill_index: 0,
+            allow_partial_fills: false,
+            payout_call: None,
+            payout_conversion: None,
+            bitcoin_hashlock: None,
+            bitcoin_timelock_preset: None,
+        }
+    }
+}
+
+#[near_bindgen]
+impl EscrowFactory {
+    /// Upgrade from the original state layout, keeping the settings. Escrows are moved
+    /// afterwards with `migrate_escrows`.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let legacy: LegacyEscrowFactory = env::state_read().expect("No factory state to migrate");
+        let mut factory = Self::new(
+            legacy.owner,
+            U128(legacy.creation_fee),
+            legacy.treasury,
+            legacy.escrow_template,
+        );
+        log!("Factory migrated, {} escrows left to move", legacy.escrow_info.len());
+        factory.legacy_escrows = Some(LegacyEscrows {
+            order_to_escrow: legacy.order_to_escrow,
+            escrow_info: legacy.escrow_info,
+        });
+        factory
+    }
+
+    /// Move up to `limit` escrows of the original layout; returns how many are left
+    #[private]
+    pub fn migrate_escrows(&mut self, limit: u64) -> u64 {
+        let mut legacy = self.legacy_escrows.take().expect("No escrows to migrate");
+        for _ in 0..limit {
+            // Taking the last entry keeps the remaining entries in place
+            let len = legacy.escrow_info.len();
+            let Some(escrow_account) = len.checked_sub(1).and_then(|last| legacy.escrow_info.keys_as_vector().get(last)) else {
+                break;
+            };
+            let info = legacy.escrow_info.remove(&escrow_account).expect("Escrow info not found");
+            legacy.order_to_escrow.remove(&info.immutables.order_hash);
+            self.insert_migrated_escrow(escrow_account, info);
+        }
+
+        let remaining = legacy.escrow_info.len();
+        if remaining > 0 {
+            self.legacy_escrows = Some(legacy);
+        }
+        log!("Migrated escrows, {} left", remaining);
+        remaining
+    }
+
+    /// Escrows of the original layout not moved yet
+    pub fn get_escrows_to_migrate(&self) -> u64 {
+        self.legacy_escrows.as_ref().map_or(0, |legacy| legacy.escrow_info.len())
+    }
+}
+
+impl EscrowFactory {
+    /// Reject calls that need the escrow registry while escrows are still being migrated
+    pub(crate) fn assert_migrated(&self) {
+        assert!(
+            self.legacy_escrows.is_none(),
+            "Escrow migration in progress, {} escrows left", self.get_escrows_to_migrate()
+        );
+    }
+
+    fn insert_migrated_escrow(&mut self, escrow_account: AccountId, info: LegacyEscrowInfo) {
+        let immutables = EscrowImmutables::from(info.immutables);
+        self.order_to_escrow.insert(
+            &order_key(&immutables.order_hash, immutables.fill_index, &info.escrow_type),
+            &escrow_account,
+        );
+        self.used_hashlocks.insert(
+            &(immutables.maker.clone(), normalize_hex(&immutables.hashlock)),
+            &(normalize_hex(&immutables.order_hash), immutables.fill_index),
+        );
+        self.escrow_info.insert(
+            &escrow_account,
+            &EscrowInfo {
+                escrow_type: info.escrow_type,
+                immutables,
+                creator: info.creator,
+                created_at: info.created_at,
+            },
+        );
+    }
+}
@@ -0,0 +1,362 @@
+//! Same-chain NEAR↔NEAR swaps settled atomically by the factory.
+//!
+//! The maker deposits its asset when opening a swap, the taker deposits the
+//! requested asset when filling it, and both legs are paid out in the same
+//! transaction. No hashlock is needed because the factory holds both deposits.
+//! A payout that fails (e.g. an unregistered NEP141 recipient) is recorded for
+//! its recipient, who can retry it with `claim_swap_payout`.
+//!
+//! NEP141 legs must be registered tokens within their limits. NEAR-opened swaps pay
+//! the record's storage out of the deposit; token-opened swaps pay it out of the
+//! maker's prior `deposit_swap_storage`.
+
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, log, near_bindgen, AccountId, Gas, NearToken, Promise, PromiseError, PromiseOrValue};
+use schemars::{gen::SchemaGenerator, schema::{Schema, SchemaObject}, JsonSchema};
+
+use shared::Balance;
+
+use crate::{EscrowFactory, EscrowFactoryExt, GAS_FOR_CALLBACK};
+
+/// Gas for NEP141 payouts of settled swaps
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_gas(20_000_000_000_000);
+
+/// Terms the maker sets when opening a same-chain swap
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NearSwapTerms {
+    /// Only this account may fill the swap (anyone if None)
+    pub taker: Option<AccountId>,
+    /// Asset the maker wants: None for NEAR, Some(account_id) for NEP141
+    pub taker_token: Option<AccountId>,
+    pub taker_amount: U128,
+    /// Unix seconds after which the swap can no longer be filled
+    pub deadline: u64,
+}
+
+impl JsonSchema for NearSwapTerms {
+    fn schema_name() -> String {
+        "NearSwapTerms".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("taker".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("taker_token".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("taker_amount".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("deadline".to_string(), gen.subschema_for::<u64>());
+        schema.object().required.extend(vec![
+            "taker".to_string(),
+            "taker_token".to_string(),
+            "taker_amount".to_string(),
+            "deadline".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+/// Open same-chain swap holding the maker's deposit
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NearSwap {
+    pub maker: AccountId,
+    pub maker_token: Option<AccountId>,
+    pub maker_amount: Balance,
+    pub taker: Option<AccountId>,
+    pub taker_token: Option<AccountId>,
+    pub taker_amount: Balance,
+    pub deadline: u64,
+}
+
+impl JsonSchema for NearSwap {
+    fn schema_name() -> String {
+        "NearSwap".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("maker".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("maker_token".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("maker_amount".to_string(), gen.subschema_for::<u128>());
+        schema.object().properties.insert("taker".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("taker_token".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("taker_amount".to_string(), gen.subschema_for::<u128>());
+        schema.object().properties.insert("deadline".to_string(), gen.subschema_for::<u64>());
+        schema.object().required.extend(vec![
+            "maker".to_string(),
+            "maker_token".to_string(),
+            "maker_amount".to_string(),
+            "taker".to_string(),
+            "taker_token".to_string(),
+            "taker_amount".to_string(),
+            "deadline".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+/// `msg` of `ft_transfer_call` to the factory
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SwapMessage {
+    /// Open a swap with the transferred tokens as the maker's deposit
+    OpenSwap(NearSwapTerms),
+    /// Fill a swap with the transferred tokens as the taker's deposit
+    FillSwap { swap_id: u64 },
+}
+
+#[near_bindgen]
+impl EscrowFactory {
+    /// Open a same-chain swap with the attached NEAR as the maker's deposit.
+    /// The storage of the swap record is paid out of the attached deposit.
+    #[payable]
+    pub fn open_near_swap(&mut self, terms: NearSwapTerms) -> u64 {
+        self.assert_migrated();
+        let initial_storage = env::storage_usage();
+        let attached = env::attached_deposit().as_yoctonear();
+        let swap_id = self.internal_open_swap(env::predecessor_account_id(), None, attached, terms);
+
+        let storage_cost = self.storage_cost_since(initial_storage);
+        assert!(
+            attached > storage_cost,
+            "Deposit must exceed the swap storage cost of {} yoctoNEAR", storage_cost
+        );
+        // The record size does not depend on the amount, so the cost stays the same
+        let mut swap = self.near_swaps.get(&swap_id).expect("Swap not found");
+        swap.maker_amount = attached - storage_cost;
+        self.near_swaps.insert(&swap_id, &swap);
+        swap_id
+    }
+
+    /// Fill a swap that asks for NEAR with the attached deposit and settle both legs
+    #[payable]
+    pub fn fill_near_swap(&mut self, swap_id: u64) -> Promise {
+        self.assert_migrated();
+        let amount = env::attached_deposit().as_yoctonear();
+        let taker = env::predecessor_account_id();
+        let swap = self.get_near_swap(swap_id).expect("Swap not found");
+        assert!(swap.taker_token.is_none(), "Swap must be filled with {:?}", swap.taker_token);
+        assert!(
+            amount >= swap.taker_amount,
+            "Insufficient deposit. Required: {}, provided: {}",
+            swap.taker_amount, amount
+        );
+
+        let settlement = self.internal_settle_swap(swap_id, taker.clone());
+        if amount > swap.taker_amount {
+            settlement.and(Promise::new(taker).transfer(NearToken::from_yoctonear(amount - swap.taker_amount)))
+        } else {
+            settlement
+        }
+    }
+
+    /// Prepay the storage of swaps the caller opens with `ft_transfer_call`.
+    /// The storage of the balance record itself is taken from the first deposit.
+    #[payable]
+    pub fn deposit_swap_storage(&mut self) -> U128 {
+        self.assert_migrated();
+        let initial_storage = env::storage_usage();
+        let account = env::predecessor_account_id();
+        let attached = env::attached_deposit().as_yoctonear();
+        let balance = self.swap_storage_deposits.get(&account).unwrap_or(0);
+        self.swap_storage_deposits.insert(&account, &balance);
+        let record_cost = self.storage_cost_since(initial_storage);
+        assert!(
+            attached > record_cost,
+            "Deposit must exceed the storage cost of {} yoctoNEAR", record_cost
+        );
+        let balance = balance + attached - record_cost;
+        self.swap_storage_deposits.insert(&account, &balance);
+        U128(balance)
+    }
+
+    /// Withdraw the caller's unused swap storage deposit
+    pub fn withdraw_swap_storage(&mut self) -> Promise {
+        let account = env::predecessor_account_id();
+        let balance = self
+            .swap_storage_deposits
+            .remove(&account)
+            .expect("No swap storage deposit");
+        Promise::new(account).transfer(NearToken::from_yoctonear(balance))
+    }
+
+    /// Unused storage deposit of `account_id` for token-opened swaps
+    pub fn get_swap_storage_deposit(&self, account_id: AccountId) -> U128 {
+        U128(self.swap_storage_deposits.get(&account_id).unwrap_or(0))
+    }
+
+    /// Cancel an unfilled swap and refund the maker's deposit (maker only)
+    pub fn cancel_near_swap(&mut self, swap_id: u64) -> Promise {
+        self.assert_migrated();
+        let swap = self.near_swaps.remove(&swap_id).expect("Swap not found");
+        assert_eq!(
+            env::predecessor_account_id(),
+            swap.maker,
+            "Only maker can cancel the swap"
+        );
+        log!("Swap {} cancelled by maker {}", swap_id, swap.maker);
+        payout(swap.maker_token, swap.maker, swap.maker_amount)
+    }
+
+    /// Retry a swap payout to the caller that failed during settlement or cancellation
+    pub fn claim_swap_payout(&mut self, token: Option<AccountId>) -> Promise {
+        let recipient = env::predecessor_account_id();
+        let amount = self
+            .unclaimed_swap_payouts
+            .remove(&(recipient.clone(), token.clone()))
+            .expect("No unclaimed swap payout");
+        payout(token, recipient, amount)
+    }
+
+    /// Swap payout owed to `recipient` in `token` (None for NEAR) after a failed transfer
+    pub fn get_unclaimed_swap_payout(&self, recipient: AccountId, token: Option<AccountId>) -> U128 {
+        U128(self.unclaimed_swap_payouts.get(&(recipient, token)).unwrap_or(0))
+    }
+
+    /// Callback of a swap payout: keep the amount for the recipient to claim if the transfer failed
+    #[private]
+    pub fn on_swap_payout(
+        &mut self,
+        token: Option<AccountId>,
+        recipient: AccountId,
+        amount: U128,
+        #[callback_result] result: Result<(), PromiseError>,
+    ) -> bool {
+        if result.is_ok() {
+            return true;
+        }
+        log!("Swap payout of {} to {} failed, kept for claiming", amount.0, recipient);
+        let key = (recipient, token);
+        let owed = self.unclaimed_swap_payouts.get(&key).unwrap_or(0);
+        self.unclaimed_swap_payouts.insert(&key, &(owed + amount.0));
+        false
+    }
+
+    pub fn get_near_swap(&self, swap_id: u64) -> Option<NearSwap> {
+        self.near_swaps.get(&swap_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for EscrowFactory {
+    /// Open or fill a same-chain swap with NEP141 tokens, see [`SwapMessage`]
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        self.assert_migrated();
+        let token = env::predecessor_account_id();
+        let message: SwapMessage =
+            near_sdk::serde_json::from_str(&msg).expect("Invalid swap message");
+
+        match message {
+            SwapMessage::OpenSwap(terms) => {
+                self.assert_supported_token(&token, amount.0);
+                let initial_storage = env::storage_usage();
+                self.internal_open_swap(sender_id.clone(), Some(token), amount.0, terms);
+
+                // The swap record's storage is paid out of the maker's storage deposit
+                let storage_cost = self.storage_cost_since(initial_storage);
+                let available = self.swap_storage_deposits.get(&sender_id).unwrap_or(0);
+                assert!(
+                    available >= storage_cost,
+                    "Insufficient swap storage deposit. Required: {}, available: {}",
+                    storage_cost, available
+                );
+                self.swap_storage_deposits.insert(&sender_id, &(available - storage_cost));
+                PromiseOrValue::Value(U128(0))
+            }
+            SwapMessage::FillSwap { swap_id } => {
+                let swap = self.get_near_swap(swap_id).expect("Swap not found");
+                assert_eq!(
+                    swap.taker_token.as_ref(),
+                    Some(&token),
+                    "Swap must be filled with {:?}", swap.taker_token
+                );
+                assert!(
+                    amount.0 >= swap.taker_amount,
+                    "Insufficient deposit. Required: {}, provided: {}",
+                    swap.taker_amount, amount.0
+                );
+                self.assert_supported_token(&token, swap.taker_amount);
+                self.internal_settle_swap(swap_id, sender_id);
+                // Unused tokens are refunded by the token contract
+                PromiseOrValue::Value(U128(amount.0 - swap.taker_amount))
+            }
+        }
+    }
+}
+
+impl EscrowFactory {
+    fn internal_open_swap(
+        &mut self,
+        maker: AccountId,
+        maker_token: Option<AccountId>,
+        maker_amount: Balance,
+        terms: NearSwapTerms,
+    ) -> u64 {
+        assert!(maker_amount > 0, "Swap amount must be positive");
+        assert!(terms.taker_amount.0 > 0, "Requested amount must be positive");
+        // A swap asking for a token nobody can fill with would only lock the maker's deposit
+        if let Some(taker_token) = &terms.taker_token {
+            self.assert_supported_token(taker_token, terms.taker_amount.0);
+        }
+        assert!(
+            env::block_timestamp() / 1_000_000_000 <= terms.deadline,
+            "Swap deadline has passed"
+        );
+
+        let swap_id = self.next_swap_id;
+        self.next_swap_id += 1;
+        self.near_swaps.insert(&swap_id, &NearSwap {
+            maker: maker.clone(),
+            maker_token,
+            maker_amount,
+            taker: terms.taker,
+            taker_token: terms.taker_token,
+            taker_amount: terms.taker_amount.0,
+            deadline: terms.deadline,
+        });
+
+        log!("Swap {} opened by maker {} for {}", swap_id, maker, maker_amount);
+        swap_id
+    }
+
+    /// Pay the maker's deposit to the taker and the taker's deposit to the maker
+    fn internal_settle_swap(&mut self, swap_id: u64, taker: AccountId) -> Promise {
+        let swap = self.near_swaps.remove(&swap_id).expect("Swap not found");
+        if let Some(allowed_taker) = &swap.taker {
+            assert_eq!(&taker, allowed_taker, "Only {} can fill this swap", allowed_taker);
+        }
+        assert!(
+            env::block_timestamp() / 1_000_000_000 <= swap.deadline,
+            "Swap deadline has passed"
+        );
+
+        log!("Swap {} filled by taker {}", swap_id, taker);
+        payout(swap.maker_token, taker, swap.maker_amount)
+            .and(payout(swap.taker_token, swap.maker, swap.taker_amount))
+    }
+}
+
+/// Transfer a swap leg, recording it for `claim_swap_payout` if the transfer fails
+fn payout(token: Option<AccountId>, recipient: AccountId, amount: Balance) -> Promise {
+    let transfer = match &token {
+        None => Promise::new(recipient.clone()).transfer(NearToken::from_yoctonear(amount)),
+        Some(token) => ext_ft_core::ext(token.clone())
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .with_attached_deposit(NearToken::from_yoctonear(1))
+            .ft_transfer(recipient.clone(), U128(amount), None),
+    };
+    transfer.then(
+        EscrowFactory::ext(env::current_account_id())
+            .with_static_gas(GAS_FOR_CALLBACK)
+            .on_swap_payout(token, recipient, U128(amount)),
+    )
+}
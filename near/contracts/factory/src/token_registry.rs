@@ -0,0 +1,199 @@
+//! Owner-managed registry of the NEP-141 tokens escrows may hold.
+//!
+//! Escrow creation with an `Ft` asset is rejected unless the token is registered
+//! and the amount and safety deposit are within its limits. Same-chain swaps opened
+//! or filled through `ft_on_transfer` are held to the same token limits. Decimals and symbol
+//! can be set by the owner or cached from the token's `ft_metadata`.
+
+use near_contract_standards::fungible_token::metadata::{ext_ft_metadata, FungibleTokenMetadata};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, log, near_bindgen, AccountId, Gas, Promise, PromiseError};
+use schemars::{gen::SchemaGenerator, schema::{Schema, SchemaObject}, JsonSchema};
+
+use shared::{Balance, EscrowAsset, EscrowImmutables};
+
+use crate::{EscrowFactory, EscrowFactoryExt, GAS_FOR_CALLBACK};
+
+/// Gas for `ft_metadata` on the token contract
+const GAS_FOR_FT_METADATA: Gas = Gas::from_gas(5_000_000_000_000);
+
+/// Limits and metadata of a registered NEP-141 token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenConfig {
+    /// Smallest escrowed amount accepted
+    pub min_amount: U128,
+    /// Largest escrowed amount accepted
+    pub max_amount: U128,
+    /// Smallest safety deposit (yoctoNEAR) accepted for escrows of this token
+    pub min_safety_deposit: U128,
+    /// Cached from `ft_metadata` when not set by the owner
+    pub decimals: Option<u8>,
+    /// Cached from `ft_metadata` when not set by the owner
+    pub symbol: Option<String>,
+}
+
+impl JsonSchema for TokenConfig {
+    fn schema_name() -> String {
+        "TokenConfig".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("min_amount".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("max_amount".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("min_safety_deposit".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("decimals".to_string(), gen.subschema_for::<Option<u8>>());
+        schema.object().properties.insert("symbol".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().required.extend(vec![
+            "min_amount".to_string(),
+            "max_amount".to_string(),
+            "min_safety_deposit".to_string(),
+            "decimals".to_string(),
+            "symbol".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+/// Registered token as listed for frontends
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SupportedToken {
+    pub token_id: AccountId,
+    pub config: TokenConfig,
+}
+
+impl JsonSchema for SupportedToken {
+    fn schema_name() -> String {
+        "SupportedToken".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("token_id".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("config".to_string(), gen.subschema_for::<TokenConfig>());
+        schema.object().required.extend(vec![
+            "token_id".to_string(),
+            "config".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+#[near_bindgen]
+impl EscrowFactory {
+    /// Register or update a NEP-141 token (owner only). Missing decimals or symbol
+    /// are fetched from the token's `ft_metadata`.
+    pub fn register_token(&mut self, token_id: AccountId, config: TokenConfig) {
+        self.assert_owner();
+        assert!(
+            config.min_amount.0 <= config.max_amount.0,
+            "Minimum amount must not exceed maximum amount"
+        );
+        assert!(config.min_safety_deposit.0 > 0, "Minimum safety deposit must be positive");
+        let fetch_metadata = config.decimals.is_none() || config.symbol.is_none();
+        self.supported_tokens.insert(&token_id, &config);
+        log!("Token registered: {}", token_id);
+
+        if fetch_metadata {
+            self.refresh_token_metadata(token_id);
+        }
+    }
+
+    /// Stop accepting a token for new escrows (owner only)
+    pub fn remove_token(&mut self, token_id: AccountId) {
+        self.assert_owner();
+        assert!(self.supported_tokens.remove(&token_id).is_some(), "Token not registered: {}", token_id);
+        log!("Token removed: {}", token_id);
+    }
+
+    /// Cache the decimals and symbol of a registered token from its `ft_metadata` (owner only)
+    pub fn refresh_token_metadata(&mut self, token_id: AccountId) -> Promise {
+        self.assert_owner();
+        assert!(self.supported_tokens.get(&token_id).is_some(), "Token not registered: {}", token_id);
+        ext_ft_metadata::ext(token_id.clone())
+            .with_static_gas(GAS_FOR_FT_METADATA)
+            .ft_metadata()
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_CALLBACK)
+                    .on_token_metadata(token_id),
+            )
+    }
+
+    /// Callback caching `ft_metadata`; values set by the owner are kept
+    #[private]
+    pub fn on_token_metadata(
+        &mut self,
+        token_id: AccountId,
+        #[callback_result] metadata: Result<FungibleTokenMetadata, PromiseError>,
+    ) -> bool {
+        let (Ok(metadata), Some(mut config)) = (metadata, self.supported_tokens.get(&token_id)) else {
+            log!("Could not cache metadata of {}", token_id);
+            return false;
+        };
+        config.decimals.get_or_insert(metadata.decimals);
+        config.symbol.get_or_insert(metadata.symbol);
+        self.supported_tokens.insert(&token_id, &config);
+        true
+    }
+
+    pub fn get_token_config(&self, token_id: AccountId) -> Option<TokenConfig> {
+        self.supported_tokens.get(&token_id)
+    }
+
+    /// Registered NEP-141 tokens with their limits and metadata, `limit` entries from `from_index`
+    pub fn get_supported_tokens(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<SupportedToken> {
+        // Indexed reads, so skipped entries are never loaded
+        let keys = self.supported_tokens.keys_as_vector();
+        let values = self.supported_tokens.values_as_vector();
+        let from_index = from_index.unwrap_or(0);
+        let end = from_index.saturating_add(limit.unwrap_or(u64::MAX)).min(keys.len());
+        (from_index..end)
+            .map(|index| SupportedToken {
+                token_id: keys.get(index).expect("Token key not found"),
+                config: values.get(index).expect("Token config not found"),
+            })
+            .collect()
+    }
+}
+
+impl EscrowFactory {
+    /// Reject NEP-141 escrows of unregistered tokens or outside the token's limits
+    pub(crate) fn check_supported_asset(&self, immutables: &EscrowImmutables) -> Result<(), String> {
+        let EscrowAsset::Ft { contract_id } = &immutables.asset else {
+            return Ok(());
+        };
+        let config = self.check_supported_token(contract_id, immutables.amount)?;
+        if immutables.safety_deposit < config.min_safety_deposit.0 {
+            return Err(format!(
+                "Safety deposit below minimum for {}: {}",
+                contract_id, config.min_safety_deposit.0
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reject amounts of unregistered NEP-141 tokens or outside the token's limits
+    pub(crate) fn check_supported_token(&self, token: &AccountId, amount: Balance) -> Result<TokenConfig, String> {
+        let config = self
+            .supported_tokens
+            .get(token)
+            .ok_or_else(|| format!("Token not supported: {}", token))?;
+        if amount < config.min_amount.0 || amount > config.max_amount.0 {
+            return Err(format!(
+                "Amount out of range for {}: {} to {}",
+                token, config.min_amount.0, config.max_amount.0
+            ));
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn assert_supported_token(&self, token: &AccountId, amount: Balance) -> TokenConfig {
+        self.check_supported_token(token, amount)
+            .unwrap_or_else(|e| env::panic_str(&e))
+    }
+}
@@ -0,0 +1,250 @@
+//! Bitcoin script HTLC compatibility.
+//!
+//! Escrows paired with a BIP-199 HTLC on Bitcoin set `bitcoin_hashlock` in their
+//! immutables. The secret is then a hex-encoded 32-byte preimage and the hashlock is
+//! the `OP_SHA256` or `OP_HASH160` digest of the raw preimage bytes, so the same
+//! secret unlocks both chains. [`htlc_script`] builds the matching Bitcoin script.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use near_sdk::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use crate::eip712::normalize_hex;
+use crate::{EscrowError, Timelocks};
+
+/// Length of the preimage Bitcoin HTLCs commit to
+pub const PREIMAGE_LENGTH: usize = 32;
+/// Shortest withdrawal period for a Bitcoin pair in seconds, six confirmations of the
+/// counterpart HTLC; escrows on the block-height clock need the equivalent in NEAR blocks
+pub const MIN_WITHDRAWAL_PERIOD: u64 = 3600;
+
+const OP_0: u8 = 0x00;
+const OP_1: u8 = 0x51;
+const OP_IF: u8 = 0x63;
+const OP_ELSE: u8 = 0x67;
+const OP_ENDIF: u8 = 0x68;
+const OP_DROP: u8 = 0x75;
+const OP_DUP: u8 = 0x76;
+const OP_EQUALVERIFY: u8 = 0x88;
+const OP_SHA256: u8 = 0xa8;
+const OP_HASH160: u8 = 0xa9;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+/// Hash opcode of the Bitcoin HTLC the escrow is paired with
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum BitcoinHashlock {
+    /// `OP_SHA256`: 32-byte SHA-256 digest
+    Sha256,
+    /// `OP_HASH160`: 20-byte RIPEMD-160 of the SHA-256 digest
+    Hash160,
+}
+
+impl BitcoinHashlock {
+    /// Digest of the raw preimage bytes
+    pub fn digest(&self, preimage: &[u8]) -> Vec<u8> {
+        let sha256 = env::sha256_array(preimage);
+        match self {
+            BitcoinHashlock::Sha256 => sha256.to_vec(),
+            BitcoinHashlock::Hash160 => env::ripemd160_array(&sha256).to_vec(),
+        }
+    }
+
+    /// Hex-encoded hashlock for a hex-encoded 32-byte preimage
+    pub fn create_hashlock(&self, secret: &str) -> Result<String, EscrowError> {
+        Ok(hex::encode(self.digest(&parse_preimage(secret)?)))
+    }
+
+    /// Whether `secret` is a 32-byte preimage of `hashlock`
+    pub fn verify_secret(&self, secret: &str, hashlock: &str) -> bool {
+        self.create_hashlock(secret)
+            .is_ok_and(|computed| computed == normalize_hex(hashlock))
+    }
+
+    fn digest_length(&self) -> usize {
+        match self {
+            BitcoinHashlock::Sha256 => 32,
+            BitcoinHashlock::Hash160 => 20,
+        }
+    }
+
+    /// Check that `hashlock` is a hex digest of this kind
+    pub fn validate_hashlock(&self, hashlock: &str) -> Result<(), EscrowError> {
+        let hashlock = normalize_hex(hashlock);
+        let valid = hashlock.len() == 2 * self.digest_length()
+            && hashlock.chars().all(|c| c.is_ascii_hexdigit());
+        if valid {
+            Ok(())
+        } else {
+            Err(EscrowError::InvalidImmutables)
+        }
+    }
+
+    fn opcode(&self) -> u8 {
+        match self {
+            BitcoinHashlock::Sha256 => OP_SHA256,
+            BitcoinHashlock::Hash160 => OP_HASH160,
+        }
+    }
+}
+
+/// Timelocks sized for Bitcoin confirmation times (about ten minutes per block)
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum BitcoinTimelockPreset {
+    /// 2h withdrawal (12 confirmations), cancellation after 4h, rescue after 7 days
+    Fast,
+    /// 12h withdrawal (72 confirmations), cancellation after 24h, rescue after 7 days
+    Standard,
+    /// 24h withdrawal (144 confirmations), cancellation after 48h, rescue after 14 days
+    Conservative,
+}
+
+impl BitcoinTimelockPreset {
+    pub fn timelocks(&self) -> Timelocks {
+        const HOUR: u64 = 3600;
+        const DAY: u64 = 24 * HOUR;
+        match self {
+            BitcoinTimelockPreset::Fast => Timelocks::new(2 * HOUR, 4 * HOUR, 7 * DAY),
+            BitcoinTimelockPreset::Standard => Timelocks::new(12 * HOUR, DAY, 7 * DAY),
+            BitcoinTimelockPreset::Conservative => Timelocks::new(DAY, 2 * DAY, 14 * DAY),
+        }
+    }
+
+    /// Set the periods of `timelocks` to this preset, converted to the timelocks' clock
+    pub fn apply(&self, timelocks: &mut Timelocks) {
+        let preset = self.timelocks();
+        let clock = timelocks.clock;
+        timelocks.withdrawal_period = clock.period_for_seconds(preset.withdrawal_period);
+        timelocks.cancellation_period = clock.period_for_seconds(preset.cancellation_period);
+        timelocks.rescue_delay = clock.period_for_seconds(preset.rescue_delay);
+    }
+}
+
+/// Decode a hex secret that must be exactly [`PREIMAGE_LENGTH`] bytes
+pub fn parse_preimage(secret: &str) -> Result<[u8; PREIMAGE_LENGTH], EscrowError> {
+    let mut preimage = [0u8; PREIMAGE_LENGTH];
+    hex::decode_to_slice(normalize_hex(secret), &mut preimage).map_err(|_| EscrowError::InvalidSecret)?;
+    Ok(preimage)
+}
+
+/// BIP-199 HTLC script paying `recipient_pubkey_hash` against the preimage of `digest`,
+/// or `refund_pubkey_hash` once `timeout` passes. The timeout is checked with
+/// `OP_CHECKSEQUENCEVERIFY` when `relative`, `OP_CHECKLOCKTIMEVERIFY` otherwise.
+pub fn htlc_script(
+    hashlock: BitcoinHashlock,
+    digest: &[u8],
+    recipient_pubkey_hash: &[u8; 20],
+    refund_pubkey_hash: &[u8; 20],
+    timeout: u32,
+    relative: bool,
+) -> Vec<u8> {
+    let mut script = vec![OP_IF, hashlock.opcode()];
+    push_data(&mut script, digest);
+    script.extend([OP_EQUALVERIFY, OP_DUP, OP_HASH160]);
+    push_data(&mut script, recipient_pubkey_hash);
+    script.push(OP_ELSE);
+    push_number(&mut script, timeout);
+    script.push(if relative { OP_CHECKSEQUENCEVERIFY } else { OP_CHECKLOCKTIMEVERIFY });
+    script.extend([OP_DROP, OP_DUP, OP_HASH160]);
+    push_data(&mut script, refund_pubkey_hash);
+    script.extend([OP_ENDIF, OP_EQUALVERIFY, OP_CHECKSIG]);
+    script
+}
+
+/// Direct push of up to 75 bytes
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    assert!(data.len() <= 75, "Push data too long");
+    script.push(data.len() as u8);
+    script.extend_from_slice(data);
+}
+
+/// Minimal push of a non-negative script number
+fn push_number(script: &mut Vec<u8>, number: u32) {
+    match number {
+        0 => script.push(OP_0),
+        1..=16 => script.push(OP_1 + number as u8 - 1),
+        _ => {
+            let mut bytes: Vec<u8> = number.to_le_bytes().to_vec();
+            while bytes.last() == Some(&0) {
+                bytes.pop();
+            }
+            // The top bit is the sign bit, so a set top bit needs an extra zero byte
+            if bytes.last().is_some_and(|byte| byte & 0x80 != 0) {
+                bytes.push(0);
+            }
+            push_data(script, &bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREIMAGE: &str = "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20";
+    const SHA256_DIGEST: &str = "ae216c2ef5247a3782c135efa279a3e4cdc61094270f5d2be58c6204b7a612c9";
+    const HASH160_DIGEST: &str = "c00f4e3c177f4f4c4aa0cf3d72dc675eabeb74a3";
+
+    #[test]
+    fn test_preimage_digests() {
+        assert_eq!(BitcoinHashlock::Sha256.create_hashlock(PREIMAGE).unwrap(), SHA256_DIGEST);
+        assert_eq!(BitcoinHashlock::Hash160.create_hashlock(PREIMAGE).unwrap(), HASH160_DIGEST);
+        assert_eq!(
+            BitcoinHashlock::Hash160.create_hashlock(&"00".repeat(32)).unwrap(),
+            "b8bcb07f6344b42ab04250c86a6e8b75d3fdbbc6"
+        );
+
+        assert!(BitcoinHashlock::Hash160.verify_secret(&format!("0x{}", PREIMAGE), HASH160_DIGEST));
+        // The preimage must be exactly 32 bytes
+        assert!(!BitcoinHashlock::Sha256.verify_secret(&PREIMAGE[2..], SHA256_DIGEST));
+        assert!(parse_preimage(&format!("{}00", PREIMAGE)).is_err());
+    }
+
+    #[test]
+    fn test_bip199_htlc_script() {
+        let digest = hex::decode(SHA256_DIGEST).unwrap();
+        let recipient = [0x11; 20];
+        let refund = [0x22; 20];
+
+        // OP_IF OP_SHA256 <digest> OP_EQUALVERIFY OP_DUP OP_HASH160 <recipient>
+        // OP_ELSE 144 OP_CHECKSEQUENCEVERIFY OP_DROP OP_DUP OP_HASH160 <refund>
+        // OP_ENDIF OP_EQUALVERIFY OP_CHECKSIG
+        let expected = format!(
+            "63a820{}8876a914{}67029000b27576a914{}6888ac",
+            SHA256_DIGEST,
+            "11".repeat(20),
+            "22".repeat(20)
+        );
+        let script = htlc_script(BitcoinHashlock::Sha256, &digest, &recipient, &refund, 144, true);
+        assert_eq!(hex::encode(script), expected);
+
+        // OP_HASH160 variant with an absolute lock time of block 800000
+        let digest = hex::decode(HASH160_DIGEST).unwrap();
+        let expected = format!(
+            "63a914{}8876a914{}670300350cb17576a914{}6888ac",
+            HASH160_DIGEST,
+            "11".repeat(20),
+            "22".repeat(20)
+        );
+        let script = htlc_script(BitcoinHashlock::Hash160, &digest, &recipient, &refund, 800_000, false);
+        assert_eq!(hex::encode(script), expected);
+    }
+
+    #[test]
+    fn test_script_numbers() {
+        let encode = |number| {
+            let mut script = Vec::new();
+            push_number(&mut script, number);
+            hex::encode(script)
+        };
+        assert_eq!(encode(0), "00");
+        assert_eq!(encode(16), "60");
+        assert_eq!(encode(17), "0111");
+        assert_eq!(encode(128), "028000");
+        assert_eq!(encode(1008), "02f003");
+    }
+}
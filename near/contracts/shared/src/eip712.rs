@@ -0,0 +1,307 @@
+//! EIP-712 hashing and signature recovery for 1inch Fusion+ orders.
+//!
+//! EVM makers sign the Limit Order Protocol `Order` struct off-chain. These helpers
+//! rebuild the exact typed-data digest on NEAR and recover the signer with
+//! `env::ecrecover`, so the factory can check an order before resolver funds are locked.
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+use near_sdk::serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
+
+use crate::EscrowError;
+
+/// Type string of the EIP-712 domain used by the Limit Order Protocol
+const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+/// Type string of the 1inch Limit Order Protocol v4 `Order` struct
+const ORDER_TYPE: &str = "Order(uint256 salt,address maker,address receiver,address makerAsset,address takerAsset,uint256 makingAmount,uint256 takingAmount,uint256 makerTraits)";
+
+/// Bit offset of the 40-bit nonce or epoch in `makerTraits` (Limit Order Protocol v4)
+const NONCE_OR_EPOCH_OFFSET: usize = 120;
+/// Width in bits of the nonce or epoch in `makerTraits`
+const NONCE_OR_EPOCH_BITS: usize = 40;
+
+/// Domain name used by the 1inch Aggregation Router
+pub const ONEINCH_DOMAIN_NAME: &str = "1inch Aggregation Router";
+/// Domain version used by the 1inch Aggregation Router v6
+pub const ONEINCH_DOMAIN_VERSION: &str = "6";
+
+/// EIP-712 domain the order was signed under
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Eip712Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub verifying_contract: String, // 0x-prefixed EVM address
+}
+
+impl Eip712Domain {
+    /// Domain of the 1inch Aggregation Router deployed at `verifying_contract`
+    pub fn oneinch(chain_id: u64, verifying_contract: String) -> Self {
+        Self {
+            name: ONEINCH_DOMAIN_NAME.to_string(),
+            version: ONEINCH_DOMAIN_VERSION.to_string(),
+            chain_id,
+            verifying_contract,
+        }
+    }
+
+    pub fn separator(&self) -> Result<[u8; 32], EscrowError> {
+        let mut encoded = Vec::with_capacity(32 * 5);
+        encoded.extend_from_slice(&env::keccak256_array(DOMAIN_TYPE.as_bytes()));
+        encoded.extend_from_slice(&env::keccak256_array(self.name.as_bytes()));
+        encoded.extend_from_slice(&env::keccak256_array(self.version.as_bytes()));
+        encoded.extend_from_slice(&u64_word(self.chain_id));
+        encoded.extend_from_slice(&address_word(&self.verifying_contract)?);
+        Ok(env::keccak256_array(&encoded))
+    }
+}
+
+/// 1inch Limit Order Protocol `Order` as signed by the maker.
+/// Addresses are 0x-prefixed hex, uint256 values are decimal or 0x-prefixed hex strings.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FusionOrder {
+    pub salt: String,
+    pub maker: String,
+    pub receiver: String,
+    pub maker_asset: String,
+    pub taker_asset: String,
+    pub making_amount: String,
+    pub taking_amount: String,
+    pub maker_traits: String,
+}
+
+impl FusionOrder {
+    pub fn struct_hash(&self) -> Result<[u8; 32], EscrowError> {
+        let mut encoded = Vec::with_capacity(32 * 9);
+        encoded.extend_from_slice(&env::keccak256_array(ORDER_TYPE.as_bytes()));
+        encoded.extend_from_slice(&uint256_word(&self.salt)?);
+        encoded.extend_from_slice(&address_word(&self.maker)?);
+        encoded.extend_from_slice(&address_word(&self.receiver)?);
+        encoded.extend_from_slice(&address_word(&self.maker_asset)?);
+        encoded.extend_from_slice(&address_word(&self.taker_asset)?);
+        encoded.extend_from_slice(&uint256_word(&self.making_amount)?);
+        encoded.extend_from_slice(&uint256_word(&self.taking_amount)?);
+        encoded.extend_from_slice(&uint256_word(&self.maker_traits)?);
+        Ok(env::keccak256_array(&encoded))
+    }
+
+    /// Nonce or epoch the maker signed in `makerTraits`
+    pub fn nonce_or_epoch(&self) -> Result<u64, EscrowError> {
+        let traits = uint256_word(&self.maker_traits)?;
+        // The word is big-endian, so bit `n` lives in byte `31 - n / 8`
+        let last = 31 - NONCE_OR_EPOCH_OFFSET / 8;
+        let first = last + 1 - NONCE_OR_EPOCH_BITS / 8;
+        let mut nonce = [0u8; 8];
+        nonce[8 - NONCE_OR_EPOCH_BITS / 8..].copy_from_slice(&traits[first..=last]);
+        Ok(u64::from_be_bytes(nonce))
+    }
+
+    /// EIP-712 digest (`keccak256(0x1901 ‖ domainSeparator ‖ structHash)`)
+    pub fn hash(&self, domain: &Eip712Domain) -> Result<[u8; 32], EscrowError> {
+        let mut encoded = Vec::with_capacity(66);
+        encoded.extend_from_slice(&[0x19, 0x01]);
+        encoded.extend_from_slice(&domain.separator()?);
+        encoded.extend_from_slice(&self.struct_hash()?);
+        Ok(env::keccak256_array(&encoded))
+    }
+}
+
+/// Order together with the maker's ECDSA signature (65-byte `r‖s‖v` or 64-byte EIP-2098 compact, hex encoded)
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SignedOrder {
+    pub order: FusionOrder,
+    pub signature: String,
+}
+
+impl SignedOrder {
+    /// Verify the signature and return the 0x-prefixed order hash
+    pub fn verify(&self, domain: &Eip712Domain) -> Result<String, EscrowError> {
+        let digest = self.order.hash(domain)?;
+        let signer = recover_signer(&digest, &self.signature)?;
+        if signer != parse_address(&self.order.maker)? {
+            return Err(EscrowError::InvalidSignature);
+        }
+        Ok(format!("0x{}", hex::encode(digest)))
+    }
+}
+
+/// Recover the EVM address that produced `signature` over `digest`
+pub fn recover_signer(digest: &[u8; 32], signature: &str) -> Result<[u8; 20], EscrowError> {
+    let bytes = hex::decode(strip_0x(signature)).map_err(|_| EscrowError::InvalidSignature)?;
+    let (rs, v) = match bytes.len() {
+        65 => {
+            let mut rs = [0u8; 64];
+            rs.copy_from_slice(&bytes[..64]);
+            let v = match bytes[64] {
+                27 | 28 => bytes[64] - 27,
+                0 | 1 => bytes[64],
+                _ => return Err(EscrowError::InvalidSignature),
+            };
+            (rs, v)
+        }
+        64 => {
+            // EIP-2098: the top bit of `s` carries the recovery id
+            let mut rs = [0u8; 64];
+            rs.copy_from_slice(&bytes);
+            let v = rs[32] >> 7;
+            rs[32] &= 0x7f;
+            (rs, v)
+        }
+        _ => return Err(EscrowError::InvalidSignature),
+    };
+
+    let public_key =
+        env::ecrecover(digest, &rs, v, true).ok_or(EscrowError::InvalidSignature)?;
+    let hash = env::keccak256_array(&public_key);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    Ok(address)
+}
+
+/// Lower-case hex without the `0x` prefix, for comparing hashes and addresses
+pub fn normalize_hex(value: &str) -> String {
+    strip_0x(value).to_ascii_lowercase()
+}
+
+/// Parse a 0x-prefixed 20-byte EVM address
+pub fn parse_address(value: &str) -> Result<[u8; 20], EscrowError> {
+    let digits = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .ok_or(EscrowError::InvalidImmutables)?;
+    let mut address = [0u8; 20];
+    hex::decode_to_slice(digits, &mut address).map_err(|_| EscrowError::InvalidImmutables)?;
+    Ok(address)
+}
+
+/// Parse a uint256 given as a decimal or 0x-prefixed hex string into a big-endian word
+pub fn uint256_word(value: &str) -> Result<[u8; 32], EscrowError> {
+    let mut word = [0u8; 32];
+    if let Some(digits) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        if digits.is_empty() || digits.len() > 64 {
+            return Err(EscrowError::InvalidImmutables);
+        }
+        let padded = format!("{:0>64}", digits);
+        hex::decode_to_slice(padded, &mut word).map_err(|_| EscrowError::InvalidImmutables)?;
+        return Ok(word);
+    }
+
+    if value.is_empty() {
+        return Err(EscrowError::InvalidImmutables);
+    }
+    for digit in value.bytes() {
+        if !digit.is_ascii_digit() {
+            return Err(EscrowError::InvalidImmutables);
+        }
+        // word = word * 10 + digit
+        let mut carry = u32::from(digit - b'0');
+        for byte in word.iter_mut().rev() {
+            let next = u32::from(*byte) * 10 + carry;
+            *byte = next as u8;
+            carry = next >> 8;
+        }
+        if carry != 0 {
+            return Err(EscrowError::InvalidImmutables);
+        }
+    }
+    Ok(word)
+}
+
+fn address_word(value: &str) -> Result<[u8; 32], EscrowError> {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(&parse_address(value)?);
+    Ok(word)
+}
+
+fn u64_word(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn strip_0x(value: &str) -> &str {
+    value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAKER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const SIGNATURE: &str = "0x335a95348caf4cfc5a992222b7efbd74065a4ad0c5cd26ffb6b719eb11b110f02c54a1c8b9c589c8722cf6d535b72a9c5b5207de429b68ef82e963812100655e1c";
+    const COMPACT_SIGNATURE: &str = "0x335a95348caf4cfc5a992222b7efbd74065a4ad0c5cd26ffb6b719eb11b110f0ac54a1c8b9c589c8722cf6d535b72a9c5b5207de429b68ef82e963812100655e";
+    const ORDER_HASH: &str = "0x7b8bf6236bd6d7e8745abf60f0cef1d7f5b397abc445ee01e52146a6f54eae17";
+
+    fn domain() -> Eip712Domain {
+        Eip712Domain::oneinch(1, "0x111111125421ca6dc452d289314280a0f8842a65".to_string())
+    }
+
+    fn order() -> FusionOrder {
+        FusionOrder {
+            salt: "42".to_string(),
+            maker: MAKER.to_string(),
+            receiver: "0x0000000000000000000000000000000000000000".to_string(),
+            maker_asset: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            taker_asset: "0xdac17f958d2ee523a2206206994597c13d831ec7".to_string(),
+            making_amount: "1000000000000000000".to_string(),
+            taking_amount: "0xb2d05e00".to_string(), // 3000 USDT
+            maker_traits: "0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_order_hash() {
+        let digest = order().hash(&domain()).unwrap();
+        assert_eq!(format!("0x{}", hex::encode(digest)), ORDER_HASH);
+    }
+
+    #[test]
+    fn test_verify_signed_order() {
+        let signed = SignedOrder { order: order(), signature: SIGNATURE.to_string() };
+        assert_eq!(signed.verify(&domain()).unwrap(), ORDER_HASH);
+
+        let compact = SignedOrder { order: order(), signature: COMPACT_SIGNATURE.to_string() };
+        assert_eq!(compact.verify(&domain()).unwrap(), ORDER_HASH);
+    }
+
+    #[test]
+    fn test_tampered_order_is_rejected() {
+        let mut tampered = order();
+        tampered.making_amount = "2000000000000000000".to_string();
+        let signed = SignedOrder { order: tampered, signature: SIGNATURE.to_string() };
+        assert!(matches!(signed.verify(&domain()), Err(EscrowError::InvalidSignature)));
+
+        let other_chain = Eip712Domain::oneinch(137, domain().verifying_contract);
+        let signed = SignedOrder { order: order(), signature: SIGNATURE.to_string() };
+        assert!(signed.verify(&other_chain).is_err());
+    }
+
+    #[test]
+    fn test_nonce_or_epoch() {
+        assert_eq!(order().nonce_or_epoch().unwrap(), 0);
+
+        let mut with_nonce = order();
+        // Nonce 0xabcdef0123 at bit 120, surrounded by set expiration and series bits
+        with_nonce.maker_traits = "0xffabcdef0123ff0000000000000000000000000000".to_string();
+        assert_eq!(with_nonce.nonce_or_epoch().unwrap(), 0xabcdef0123);
+    }
+
+    #[test]
+    fn test_uint256_word() {
+        assert_eq!(uint256_word("256").unwrap(), uint256_word("0x100").unwrap());
+        assert!(uint256_word("12a").is_err());
+        assert!(uint256_word(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936"
+        )
+        .is_err()); // 2^256
+    }
+}
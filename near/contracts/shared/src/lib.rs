@@ -1,12 +1,21 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::{AccountId, Timestamp};
+use near_sdk::{AccountId, BlockHeight, Timestamp};
 use sha2::{Digest, Sha256};
 use schemars::{JsonSchema, gen::SchemaGenerator, schema::{Schema, SchemaObject}};
 
+pub mod bitcoin;
+pub mod eip712;
+
+pub use bitcoin::{BitcoinHashlock, BitcoinTimelockPreset};
+pub use eip712::{Eip712Domain, FusionOrder, SignedOrder};
+
 // Type alias for compatibility
 pub type Balance = u128;
 
+/// Chain id used for NEAR in `src_chain_id`/`dst_chain_id` (SLIP-44 coin type)
+pub const NEAR_CHAIN_ID: u64 = 397;
+
 /// Type of escrow contract - determines permissions and behavior
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(crate = "near_sdk::serde")]
@@ -17,6 +26,93 @@ pub enum EscrowType {
     Destination,
 }
 
+/// Asset held by an escrow
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EscrowAsset {
+    /// Native NEAR
+    Near,
+    /// NEP-141 fungible token
+    Ft { contract_id: AccountId },
+    /// NEP-171 non-fungible token (`amount` must be 1)
+    Nft { contract_id: AccountId, token_id: String },
+    /// NEP-245 multi-token
+    Mt { contract_id: AccountId, token_id: String },
+}
+
+impl EscrowAsset {
+    pub fn is_near(&self) -> bool {
+        matches!(self, EscrowAsset::Near)
+    }
+
+    /// Contract of a NEP-141 or NEP-171 asset
+    pub fn contract_id(&self) -> Option<&AccountId> {
+        match self {
+            EscrowAsset::Near => None,
+            EscrowAsset::Ft { contract_id }
+            | EscrowAsset::Nft { contract_id, .. }
+            | EscrowAsset::Mt { contract_id, .. } => Some(contract_id),
+        }
+    }
+}
+
+/// Mirror of [`EscrowAsset`] with account IDs as strings, used for its JSON schema
+#[derive(JsonSchema)]
+#[schemars(rename = "EscrowAsset")]
+#[allow(dead_code)]
+enum EscrowAssetSchema {
+    Near,
+    Ft { contract_id: String },
+    Nft { contract_id: String, token_id: String },
+    Mt { contract_id: String, token_id: String },
+}
+
+impl JsonSchema for EscrowAsset {
+    fn schema_name() -> String {
+        "EscrowAsset".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        EscrowAssetSchema::json_schema(gen)
+    }
+}
+
+/// `ft_transfer_call` target for a NEP141 payout: tokens go to `receiver_id` with `msg`,
+/// and whatever it refunds is forwarded to the payout recipient
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PayoutCall {
+    pub receiver_id: AccountId,
+    pub msg: String,
+}
+
+impl JsonSchema for PayoutCall {
+    fn schema_name() -> String {
+        "PayoutCall".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = SchemaObject::default();
+        schema.object().properties.insert("receiver_id".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("msg".to_string(), gen.subschema_for::<String>());
+        schema.object().required.extend(vec![
+            "receiver_id".to_string(),
+            "msg".to_string()
+        ]);
+        Schema::Object(schema)
+    }
+}
+
+/// Conversion between native NEAR and wNEAR applied to withdrawal payouts
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum NearConversion {
+    /// Escrow holds native NEAR, the recipient receives wNEAR (`near_deposit`)
+    Wrap,
+    /// Escrow holds wNEAR, the recipient receives native NEAR (`near_withdraw`)
+    Unwrap,
+}
+
 /// Immutable parameters for escrow contracts that match EVM structure
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
 #[serde(crate = "near_sdk::serde")]
@@ -25,10 +121,121 @@ pub struct EscrowImmutables {
     pub hashlock: String,      // SHA-256 hash of secret (hex encoded)
     pub maker: AccountId,      // Account creating the order
     pub taker: AccountId,      // Account filling the order
-    pub token: Option<AccountId>, // None for NEAR, Some(account_id) for NEP141
+    pub asset: EscrowAsset,    // Native NEAR, NEP141 or NEP171 asset held by the escrow
     pub amount: Balance,       // Amount in yoctoNEAR or token units
     pub safety_deposit: Balance, // Safety deposit amount
     pub timelocks: Timelocks,
+    #[serde(default)]
+    pub nonce: u64,            // Nonce or epoch from the signed order's makerTraits
+    pub src_chain_id: u64,     // Chain the maker's assets come from
+    pub dst_chain_id: u64,     // Chain the maker receives assets on
+    pub counterpart_token: String,  // Asset of the other leg (0x address or NEAR account)
+    pub counterpart_amount: String, // Amount of the other leg (decimal uint256)
+    pub evm_maker: Option<String>,    // Maker's EVM address, if the order was signed on EVM
+    pub evm_receiver: Option<String>, // EVM address receiving the other leg
+    pub deadline: u64,         // Order expiry (Unix seconds), no escrow can be created after it
+    #[serde(default)]
+    pub receiver: Option<AccountId>, // Account receiving the maker's withdrawal, defaults to maker
+    #[serde(default)]
+    pub fill_index: u64,       // Index of the secret used for this fill (0 for single-fill orders)
+    #[serde(default)]
+    pub allow_partial_fills: bool, // Merkle partial-fill order: fills of this order may share a hashlock
+    #[serde(default)]
+    pub payout_call: Option<PayoutCall>, // Maker's `ft_transfer_call` for its withdrawal (NEP141 only)
+    #[serde(default)]
+    pub payout_conversion: Option<NearConversion>, // Wrap or unwrap NEAR on withdrawal (refunds are unchanged)
+    #[serde(default)]
+    pub bitcoin_hashlock: Option<BitcoinHashlock>, // Bitcoin HTLC pair: hashlock over a raw 32-byte preimage
+    #[serde(default)]
+    pub bitcoin_timelock_preset: Option<BitcoinTimelockPreset>, // Bitcoin pair: timelock periods set by `validate`
+}
+
+impl EscrowImmutables {
+    /// Check the cross-chain order fields before an escrow is created, first setting the
+    /// timelock periods from the Bitcoin preset if one is chosen
+    pub fn validate(&mut self) -> Result<(), EscrowError> {
+        let near_leg = self.src_chain_id == NEAR_CHAIN_ID || self.dst_chain_id == NEAR_CHAIN_ID;
+        let same_chain = self.src_chain_id == self.dst_chain_id;
+        if !near_leg || (same_chain && self.src_chain_id != NEAR_CHAIN_ID) {
+            return Err(EscrowError::InvalidImmutables);
+        }
+
+        if self.payout_call.is_some() && !matches!(self.asset, EscrowAsset::Ft { .. }) {
+            return Err(EscrowError::InvalidImmutables);
+        }
+        match &self.asset {
+            EscrowAsset::Near | EscrowAsset::Ft { .. } => {}
+            // An NFT is a single indivisible unit
+            EscrowAsset::Nft { token_id, .. } => {
+                if token_id.is_empty() || self.amount != 1 {
+                    return Err(EscrowError::InvalidImmutables);
+                }
+            }
+            EscrowAsset::Mt { token_id, .. } => {
+                if token_id.is_empty() || self.amount == 0 {
+                    return Err(EscrowError::InvalidImmutables);
+                }
+            }
+        }
+
+        if let Some(preset) = &self.bitcoin_timelock_preset {
+            if self.bitcoin_hashlock.is_none() {
+                return Err(EscrowError::InvalidImmutables);
+            }
+            preset.apply(&mut self.timelocks);
+        }
+        if let Some(bitcoin_hashlock) = &self.bitcoin_hashlock {
+            bitcoin_hashlock.validate_hashlock(&self.hashlock)?;
+            // The counterpart HTLC needs time to confirm before the secret is revealed
+            let min_withdrawal_period = self.timelocks.clock.period_for_seconds(bitcoin::MIN_WITHDRAWAL_PERIOD);
+            if self.timelocks.withdrawal_period < min_withdrawal_period {
+                return Err(EscrowError::InvalidImmutables);
+            }
+        }
+
+        if self.counterpart_token.is_empty() {
+            return Err(EscrowError::InvalidImmutables);
+        }
+        eip712::uint256_word(&self.counterpart_amount)?;
+        for address in [&self.evm_maker, &self.evm_receiver].into_iter().flatten() {
+            eip712::parse_address(address)?;
+        }
+
+        // The escrow is stamped with the current block, so its stages must fit from now on
+        self.timelocks.stages_from(self.timelocks.clock.now())?;
+
+        if near_sdk::env::block_timestamp() / NANOS_PER_SECOND > self.deadline {
+            return Err(EscrowError::OrderExpired);
+        }
+        Ok(())
+    }
+
+    /// Whether `secret` unlocks the hashlock, as a Bitcoin preimage when `bitcoin_hashlock` is set
+    pub fn verify_secret(&self, secret: &str) -> bool {
+        match &self.bitcoin_hashlock {
+            Some(bitcoin_hashlock) => bitcoin_hashlock.verify_secret(secret, &self.hashlock),
+            None => CryptoUtils::verify_secret(secret, &self.hashlock),
+        }
+    }
+
+    /// Check the payout conversion against the wNEAR contract configured in the factory
+    pub fn validate_conversion(&self, wnear: Option<&AccountId>) -> Result<(), EscrowError> {
+        let Some(conversion) = &self.payout_conversion else {
+            return Ok(());
+        };
+        let wnear = wnear.ok_or(EscrowError::InvalidImmutables)?;
+        let valid = match conversion {
+            NearConversion::Wrap => self.asset.is_near(),
+            NearConversion::Unwrap => {
+                self.asset == EscrowAsset::Ft { contract_id: wnear.clone() } && self.payout_call.is_none()
+            }
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(EscrowError::InvalidImmutables)
+        }
+    }
 }
 
 impl JsonSchema for EscrowImmutables {
@@ -42,57 +249,273 @@ impl JsonSchema for EscrowImmutables {
         schema.object().properties.insert("hashlock".to_string(), gen.subschema_for::<String>());
         schema.object().properties.insert("maker".to_string(), gen.subschema_for::<String>());
         schema.object().properties.insert("taker".to_string(), gen.subschema_for::<String>());
-        schema.object().properties.insert("token".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("asset".to_string(), gen.subschema_for::<EscrowAsset>());
         schema.object().properties.insert("amount".to_string(), gen.subschema_for::<u128>());
         schema.object().properties.insert("safety_deposit".to_string(), gen.subschema_for::<u128>());
         schema.object().properties.insert("timelocks".to_string(), gen.subschema_for::<Timelocks>());
+        schema.object().properties.insert("nonce".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("src_chain_id".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("dst_chain_id".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("counterpart_token".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("counterpart_amount".to_string(), gen.subschema_for::<String>());
+        schema.object().properties.insert("evm_maker".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("evm_receiver".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("deadline".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("receiver".to_string(), gen.subschema_for::<Option<String>>());
+        schema.object().properties.insert("fill_index".to_string(), gen.subschema_for::<u64>());
+        schema.object().properties.insert("allow_partial_fills".to_string(), gen.subschema_for::<bool>());
+        schema.object().properties.insert("payout_call".to_string(), gen.subschema_for::<Option<PayoutCall>>());
+        schema.object().properties.insert("payout_conversion".to_string(), gen.subschema_for::<Option<NearConversion>>());
+        schema.object().properties.insert("bitcoin_hashlock".to_string(), gen.subschema_for::<Option<BitcoinHashlock>>());
+        schema.object().properties.insert("bitcoin_timelock_preset".to_string(), gen.subschema_for::<Option<BitcoinTimelockPreset>>());
         schema.object().required.extend(vec![
             "order_hash".to_string(), 
             "hashlock".to_string(), 
             "maker".to_string(), 
             "taker".to_string(), 
-            "token".to_string(), 
+            "asset".to_string(), 
             "amount".to_string(), 
             "safety_deposit".to_string(),
-            "timelocks".to_string()
+            "timelocks".to_string(),
+            "src_chain_id".to_string(),
+            "dst_chain_id".to_string(),
+            "counterpart_token".to_string(),
+            "counterpart_amount".to_string(),
+            "evm_maker".to_string(),
+            "evm_receiver".to_string(),
+            "deadline".to_string()
         ]);
         Schema::Object(schema)
     }
 }
 
+/// Nanoseconds per second of the timelock periods
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+/// Shortest NEAR block time (milliseconds). Block counts derived from durations assume it,
+/// so they never last shorter than the duration.
+pub const MIN_NEAR_BLOCK_TIME_MS: u64 = 600;
+/// Nanoseconds per block at `MIN_NEAR_BLOCK_TIME_MS`
+const NANOS_PER_MIN_BLOCK: u64 = MIN_NEAR_BLOCK_TIME_MS * 1_000_000;
+
+/// Clock the timelock periods are measured in
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TimelockClock {
+    /// Periods in seconds from `deployed_at` (block timestamp)
+    #[default]
+    Timestamp,
+    /// Periods in blocks from `deployed_at_height`, as Bitcoin HTLCs count them
+    BlockHeight,
+}
+
+impl TimelockClock {
+    /// Current reading of the clock: block timestamp in nanoseconds or block height
+    pub fn now(&self) -> u64 {
+        match self {
+            TimelockClock::Timestamp => near_sdk::env::block_timestamp(),
+            TimelockClock::BlockHeight => near_sdk::env::block_height(),
+        }
+    }
+
+    /// Timelock period in this clock's unit covering at least `seconds`
+    pub fn period_for_seconds(&self, seconds: u64) -> u64 {
+        match self {
+            TimelockClock::Timestamp => seconds,
+            TimelockClock::BlockHeight => seconds.saturating_mul(1000).div_ceil(MIN_NEAR_BLOCK_TIME_MS),
+        }
+    }
+
+    /// Ticks of the other clock spanned by `ticks` of this one, converting at
+    /// `MIN_NEAR_BLOCK_TIME_MS` per block: the most blocks a duration can last,
+    /// or the least time a number of blocks can take
+    pub fn ticks_on_other_clock(&self, ticks: u64) -> u64 {
+        match self {
+            TimelockClock::Timestamp => ticks.div_ceil(NANOS_PER_MIN_BLOCK),
+            TimelockClock::BlockHeight => ticks.saturating_mul(NANOS_PER_MIN_BLOCK),
+        }
+    }
+
+    /// Clock ticks per unit of a timelock period
+    fn ticks_per_period_unit(&self) -> u64 {
+        match self {
+            TimelockClock::Timestamp => NANOS_PER_SECOND,
+            TimelockClock::BlockHeight => 1,
+        }
+    }
+}
+
 /// Timelock configuration matching EVM implementation
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, JsonSchema)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Timelocks {
+    /// Set by the escrow from the block timestamp on deployment; any client value is ignored
+    #[serde(default)]
     pub deployed_at: Timestamp,
-    pub withdrawal_period: u64,    // Duration in seconds for withdrawal
-    pub cancellation_period: u64,  // Duration in seconds for cancellation
+    /// Set by the escrow from the block height on deployment; any client value is ignored
+    #[serde(default)]
+    pub deployed_at_height: BlockHeight,
+    /// Clock the periods below are measured in
+    #[serde(default)]
+    pub clock: TimelockClock,
+    pub withdrawal_period: u64,    // Duration in seconds (or blocks) for withdrawal
+    pub cancellation_period: u64,  // Duration in seconds (or blocks) for cancellation
     pub rescue_delay: u64,         // Delay before funds can be rescued
 }
 
+/// Points at which the escrow stages change, as readings of the escrow's clock
+/// (block timestamps in nanoseconds, or block heights)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockStages {
+    /// Last reading at which withdrawal is allowed
+    pub withdrawal_end: u64,
+    /// First reading at which cancellation is allowed
+    pub cancellation_start: u64,
+    /// First reading at which funds can be rescued
+    pub rescue_start: u64,
+}
+
+/// Stage boundaries on both clocks: exact on the escrow's clock and converted at
+/// `MIN_NEAR_BLOCK_TIME_MS` on the other one
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimelockSchedule {
+    /// Clock the escrow measures its stages in
+    pub clock: TimelockClock,
+    /// Boundaries as block timestamps (nanoseconds)
+    pub timestamps: TimelockStages,
+    /// Boundaries as block heights
+    pub block_heights: TimelockStages,
+}
+
+impl TimelockSchedule {
+    /// Boundaries on the escrow's own clock
+    pub fn native(&self) -> &TimelockStages {
+        match self.clock {
+            TimelockClock::Timestamp => &self.timestamps,
+            TimelockClock::BlockHeight => &self.block_heights,
+        }
+    }
+}
+
+/// Period of the escrow timeline
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TimelockStage {
+    /// Withdrawal with the secret is allowed
+    Withdrawal,
+    /// Withdrawal has ended and cancellation has not started yet
+    AwaitingCancellation,
+    /// Cancellation is allowed
+    Cancellation,
+    /// Cancellation and rescue of stray funds are allowed
+    Rescue,
+}
+
+impl TimelockStages {
+    /// Stage the escrow is in at `now`
+    pub fn stage_at(&self, now: u64) -> TimelockStage {
+        if now >= self.rescue_start {
+            TimelockStage::Rescue
+        } else if now >= self.cancellation_start {
+            TimelockStage::Cancellation
+        } else if now <= self.withdrawal_end {
+            TimelockStage::Withdrawal
+        } else {
+            TimelockStage::AwaitingCancellation
+        }
+    }
+
+    /// Boundary ending the stage the escrow is in at `now`, if any
+    pub fn next_boundary(&self, now: u64) -> Option<u64> {
+        match self.stage_at(now) {
+            TimelockStage::Withdrawal => Some(self.withdrawal_end.min(self.cancellation_start)),
+            TimelockStage::AwaitingCancellation => Some(self.cancellation_start),
+            TimelockStage::Cancellation => Some(self.rescue_start),
+            TimelockStage::Rescue => None,
+        }
+    }
+}
+
 impl Timelocks {
     pub fn new(withdrawal_period: u64, cancellation_period: u64, rescue_delay: u64) -> Self {
         Self {
             deployed_at: near_sdk::env::block_timestamp(),
+            deployed_at_height: near_sdk::env::block_height(),
+            clock: TimelockClock::Timestamp,
             withdrawal_period,
             cancellation_period,
             rescue_delay,
         }
     }
 
+    /// Record the current block as the deployment of the escrow
+    pub fn stamp_deployment(&mut self) {
+        self.deployed_at = near_sdk::env::block_timestamp();
+        self.deployed_at_height = near_sdk::env::block_height();
+    }
+
+    /// Stage boundaries relative to the deployment, in the escrow's clock
+    pub fn stages(&self) -> Result<TimelockStages, EscrowError> {
+        self.stages_from(match self.clock {
+            TimelockClock::Timestamp => self.deployed_at,
+            TimelockClock::BlockHeight => self.deployed_at_height,
+        })
+    }
+
+    /// Stage boundaries on both clocks, converting the escrow's own at `MIN_NEAR_BLOCK_TIME_MS`
+    pub fn schedule(&self) -> Result<TimelockSchedule, EscrowError> {
+        let stages = self.stages()?;
+        let (deployed, deployed_on_other) = match self.clock {
+            TimelockClock::Timestamp => (self.deployed_at, self.deployed_at_height),
+            TimelockClock::BlockHeight => (self.deployed_at_height, self.deployed_at),
+        };
+        let convert = |boundary: u64| {
+            deployed_on_other.saturating_add(self.clock.ticks_on_other_clock(boundary - deployed))
+        };
+        let converted = TimelockStages {
+            withdrawal_end: convert(stages.withdrawal_end),
+            cancellation_start: convert(stages.cancellation_start),
+            rescue_start: convert(stages.rescue_start),
+        };
+        let (timestamps, block_heights) = match self.clock {
+            TimelockClock::Timestamp => (stages, converted),
+            TimelockClock::BlockHeight => (converted, stages),
+        };
+        Ok(TimelockSchedule { clock: self.clock, timestamps, block_heights })
+    }
+
+    /// Stage boundaries for an escrow deployed at clock reading `deployed`, failing if any overflows
+    pub fn stages_from(&self, deployed: u64) -> Result<TimelockStages, EscrowError> {
+        let ticks = self.clock.ticks_per_period_unit();
+        let stage = |period: u64| {
+            period
+                .checked_mul(ticks)
+                .and_then(|period| deployed.checked_add(period))
+                .ok_or(EscrowError::InvalidTime)
+        };
+        Ok(TimelockStages {
+            withdrawal_end: stage(self.withdrawal_period)?,
+            cancellation_start: stage(self.cancellation_period)?,
+            rescue_start: stage(self.rescue_delay)?,
+        })
+    }
+
     pub fn can_withdraw(&self) -> bool {
-        let current_time = near_sdk::env::block_timestamp();
-        current_time <= self.deployed_at + self.withdrawal_period * 1_000_000_000 // Convert to nanoseconds
+        self.clock.now() <= self.checked_stages().withdrawal_end
     }
 
     pub fn can_cancel(&self) -> bool {
-        let current_time = near_sdk::env::block_timestamp();
-        current_time >= self.deployed_at + self.cancellation_period * 1_000_000_000
+        self.clock.now() >= self.checked_stages().cancellation_start
     }
 
     pub fn can_rescue(&self) -> bool {
-        let current_time = near_sdk::env::block_timestamp();
-        current_time >= self.deployed_at + self.rescue_delay * 1_000_000_000
+        self.clock.now() >= self.checked_stages().rescue_start
+    }
+
+    fn checked_stages(&self) -> TimelockStages {
+        self.stages()
+            .unwrap_or_else(|e| near_sdk::env::panic_str(&e.to_string()))
     }
 }
 
@@ -132,6 +555,8 @@ pub enum EscrowError {
     StorageDepositRequired,
     InvalidImmutables,
     TransferFailed,
+    InvalidSignature,
+    OrderExpired,
 }
 
 impl std::fmt::Display for EscrowError {
@@ -144,10 +569,51 @@ impl std::fmt::Display for EscrowError {
             EscrowError::StorageDepositRequired => write!(f, "Storage deposit required"),
             EscrowError::InvalidImmutables => write!(f, "Invalid immutables"),
             EscrowError::TransferFailed => write!(f, "Transfer failed"),
+            EscrowError::InvalidSignature => write!(f, "Invalid order signature"),
+            EscrowError::OrderExpired => write!(f, "Order deadline has passed"),
         }
     }
 }
 
+/// Hex digits of the order fill hash kept in escrow account names (128 bits)
+pub const ESCROW_NAME_HASH_LEN: usize = 32;
+
+/// Deterministic account ID of the escrow a factory creates for one leg of an order fill:
+/// `{src|dst}-<first 32 hex digits of sha256("<order hash>:<fill_index>")>.<factory>`,
+/// with the order hash lowercased and without `0x`
+pub fn escrow_account_id(
+    factory: &AccountId,
+    order_hash: &str,
+    fill_index: u64,
+    escrow_type: &EscrowType,
+) -> AccountId {
+    let type_prefix = match escrow_type {
+        EscrowType::Source => "src",
+        EscrowType::Destination => "dst",
+    };
+    let fill_hash = CryptoUtils::create_hashlock(&format!("{}:{}", eip712::normalize_hex(order_hash), fill_index));
+
+    format!("{}-{}.{}", type_prefix, &fill_hash[..ESCROW_NAME_HASH_LEN], factory)
+        .parse()
+        .expect("Factory account ID too long for escrow accounts")
+}
+
+/// NEP-297 standard name of events emitted by the escrow contracts
+pub const EVENT_STANDARD: &str = "nearfusion";
+/// NEP-297 version of events emitted by the escrow contracts
+pub const EVENT_VERSION: &str = "1.0.0";
+
+/// Log a NEP-297 event (`EVENT_JSON:{...}`) so indexers and relayers can follow it
+pub fn emit_event(event: &str, data: near_sdk::serde_json::Value) {
+    let event = near_sdk::serde_json::json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_VERSION,
+        "event": event,
+        "data": [data],
+    });
+    near_sdk::env::log_str(&format!("EVENT_JSON:{}", event));
+}
+
 /// Storage deposit calculation for NEAR
 pub fn calculate_storage_deposit() -> Balance {
     // Approximately 0.1 NEAR for storage deposit
@@ -167,6 +633,18 @@ mod tests {
         assert!(!CryptoUtils::verify_secret("wrong_secret", &hashlock));
     }
 
+    #[test]
+    fn test_escrow_account_id() {
+        let factory: AccountId = "factory.near".parse().unwrap();
+        let source = escrow_account_id(&factory, "0xABCDEF0123", 0, &EscrowType::Source);
+        assert_eq!(source, escrow_account_id(&factory, "abcdef0123", 0, &EscrowType::Source));
+        assert_eq!(source.as_str().len(), "src-".len() + ESCROW_NAME_HASH_LEN + ".factory.near".len());
+        // Orders sharing a prefix and other fills get their own accounts
+        assert_ne!(source, escrow_account_id(&factory, "0xabcdef0124", 0, &EscrowType::Source));
+        assert_ne!(source, escrow_account_id(&factory, "0xabcdef0123", 1, &EscrowType::Source));
+        assert_ne!(source, escrow_account_id(&factory, "0xabcdef0123", 0, &EscrowType::Destination));
+    }
+
     #[test]
     fn test_timelocks() {
         let timelocks = Timelocks::new(3600, 7200, 86400); // 1h, 2h, 24h
@@ -180,4 +658,190 @@ mod tests {
         // Cannot rescue initially
         assert!(!timelocks.can_rescue());
     }
+
+    #[test]
+    fn test_timelock_stages() {
+        let mut timelocks = Timelocks::new(3600, 7200, 86400);
+        timelocks.deployed_at = 1_000;
+        assert_eq!(
+            timelocks.stages().unwrap(),
+            TimelockStages {
+                withdrawal_end: 1_000 + 3600 * NANOS_PER_SECOND,
+                cancellation_start: 1_000 + 7200 * NANOS_PER_SECOND,
+                rescue_start: 1_000 + 86400 * NANOS_PER_SECOND,
+            }
+        );
+
+        let stages = timelocks.stages().unwrap();
+        assert_eq!(stages.stage_at(1_000), TimelockStage::Withdrawal);
+        assert_eq!(stages.next_boundary(1_000), Some(stages.withdrawal_end));
+        assert_eq!(stages.stage_at(stages.withdrawal_end + 1), TimelockStage::AwaitingCancellation);
+        assert_eq!(stages.stage_at(stages.cancellation_start), TimelockStage::Cancellation);
+        assert_eq!(stages.stage_at(stages.rescue_start), TimelockStage::Rescue);
+        assert_eq!(stages.next_boundary(stages.rescue_start), None);
+
+        timelocks.rescue_delay = u64::MAX / NANOS_PER_SECOND + 1;
+        assert!(timelocks.stages().is_err());
+        timelocks.rescue_delay = 86400;
+        timelocks.deployed_at = u64::MAX;
+        assert!(timelocks.stages().is_err());
+    }
+
+    #[test]
+    fn test_block_height_timelocks() {
+        let mut timelocks = Timelocks::new(100, 200, 1000);
+        timelocks.clock = TimelockClock::BlockHeight;
+        timelocks.deployed_at_height = 50;
+        assert_eq!(
+            timelocks.stages().unwrap(),
+            TimelockStages { withdrawal_end: 150, cancellation_start: 250, rescue_start: 1050 }
+        );
+
+        // Far in the future by timestamp, which the block-height clock ignores
+        near_sdk::testing_env!(near_sdk::test_utils::VMContextBuilder::new()
+            .block_height(250)
+            .block_timestamp(u64::MAX / 2)
+            .build());
+        assert!(!timelocks.can_withdraw());
+        assert!(timelocks.can_cancel());
+        assert!(!timelocks.can_rescue());
+    }
+
+    #[test]
+    fn test_timelock_schedule_converts_clocks() {
+        let mut timelocks = Timelocks::new(60, 120, 600);
+        timelocks.deployed_at = 1_000;
+        timelocks.deployed_at_height = 50;
+
+        // 60s last at most 100 blocks of 600ms
+        let schedule = timelocks.schedule().unwrap();
+        assert_eq!(schedule.native(), &schedule.timestamps);
+        assert_eq!(schedule.timestamps.withdrawal_end, 1_000 + 60 * NANOS_PER_SECOND);
+        assert_eq!(
+            schedule.block_heights,
+            TimelockStages { withdrawal_end: 150, cancellation_start: 250, rescue_start: 1050 }
+        );
+
+        // 100 blocks take at least 60s
+        timelocks.clock = TimelockClock::BlockHeight;
+        timelocks.withdrawal_period = 100;
+        let schedule = timelocks.schedule().unwrap();
+        assert_eq!(schedule.native(), &schedule.block_heights);
+        assert_eq!(schedule.block_heights.withdrawal_end, 150);
+        assert_eq!(schedule.timestamps.withdrawal_end, 1_000 + 60 * NANOS_PER_SECOND);
+    }
+
+    fn test_immutables() -> EscrowImmutables {
+        EscrowImmutables {
+            order_hash: "order_123".to_string(),
+            hashlock: CryptoUtils::create_hashlock("test_secret_123"),
+            maker: "maker.near".parse().unwrap(),
+            taker: "taker.near".parse().unwrap(),
+            asset: EscrowAsset::Near,
+            amount: 1_000_000_000_000_000_000_000_000,
+            safety_deposit: 100_000_000_000_000_000_000_000,
+            timelocks: Timelocks::new(3600, 7200, 86400),
+            nonce: 0,
+            src_chain_id: 1,
+            dst_chain_id: NEAR_CHAIN_ID,
+            counterpart_token: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            counterpart_amount: "400000000000000000".to_string(),
+            evm_maker: Some("0x2c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string()),
+            evm_receiver: None,
+            deadline: 3600,
+            receiver: None,
+            fill_index: 0,
+            allow_partial_fills: false,
+            payout_call: None,
+            payout_conversion: None,
+            bitcoin_hashlock: None,
+            bitcoin_timelock_preset: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_immutables() {
+        assert!(test_immutables().validate().is_ok());
+
+        let mut evm_only = test_immutables();
+        evm_only.dst_chain_id = 137;
+        assert!(evm_only.validate().is_err());
+
+        let mut nft = test_immutables();
+        nft.asset = EscrowAsset::Nft {
+            contract_id: "nft.near".parse().unwrap(),
+            token_id: "1".to_string(),
+        };
+        assert!(nft.validate().is_err());
+        nft.amount = 1;
+        assert!(nft.validate().is_ok());
+
+        let mut mt = test_immutables();
+        mt.asset = EscrowAsset::Mt {
+            contract_id: "intents.near".parse().unwrap(),
+            token_id: String::new(),
+        };
+        assert!(mt.validate().is_err());
+
+        let mut bad_maker = test_immutables();
+        bad_maker.evm_maker = Some("0x1234".to_string());
+        assert!(bad_maker.validate().is_err());
+
+        let mut bitcoin = test_immutables();
+        bitcoin.bitcoin_hashlock = Some(BitcoinHashlock::Hash160);
+        // A SHA-256 hashlock does not fit OP_HASH160
+        assert!(bitcoin.validate().is_err());
+        bitcoin.hashlock = "c00f4e3c177f4f4c4aa0cf3d72dc675eabeb74a3".to_string();
+        assert!(bitcoin.validate().is_ok());
+        bitcoin.timelocks.withdrawal_period = 600;
+        assert!(bitcoin.validate().is_err());
+        // An hour of NEAR blocks is more blocks than seconds
+        bitcoin.timelocks.clock = TimelockClock::BlockHeight;
+        bitcoin.timelocks.withdrawal_period = 3600;
+        assert!(bitcoin.validate().is_err());
+        bitcoin.timelocks.withdrawal_period = 6000;
+        bitcoin.timelocks.cancellation_period = 12000;
+        bitcoin.timelocks.rescue_delay = 86400;
+        assert!(bitcoin.validate().is_ok());
+
+        // The preset sets the periods in the escrow's clock
+        bitcoin.bitcoin_timelock_preset = Some(BitcoinTimelockPreset::Fast);
+        bitcoin.timelocks.withdrawal_period = 1;
+        assert!(bitcoin.validate().is_ok());
+        assert_eq!(bitcoin.timelocks.withdrawal_period, 12_000); // 2h of 600ms blocks
+        bitcoin.timelocks.clock = TimelockClock::Timestamp;
+        assert!(bitcoin.validate().is_ok());
+        assert_eq!(bitcoin.timelocks.withdrawal_period, 7200);
+
+        let mut preset_only = test_immutables();
+        preset_only.bitcoin_timelock_preset = Some(BitcoinTimelockPreset::Standard);
+        assert!(preset_only.validate().is_err());
+
+        let mut overflowing = test_immutables();
+        overflowing.timelocks.cancellation_period = u64::MAX;
+        assert!(overflowing.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_conversion() {
+        let wnear: AccountId = "wrap.near".parse().unwrap();
+        let mut wrap = test_immutables();
+        wrap.payout_conversion = Some(NearConversion::Wrap);
+        assert!(wrap.validate_conversion(Some(&wnear)).is_ok());
+        assert!(wrap.validate_conversion(None).is_err());
+
+        let mut unwrap = test_immutables();
+        unwrap.payout_conversion = Some(NearConversion::Unwrap);
+        assert!(unwrap.validate_conversion(Some(&wnear)).is_err());
+        unwrap.asset = EscrowAsset::Ft { contract_id: wnear.clone() };
+        assert!(unwrap.validate_conversion(Some(&wnear)).is_ok());
+    }
+
+    #[test]
+    fn test_expired_order_is_rejected() {
+        let mut context = near_sdk::test_utils::VMContextBuilder::new();
+        near_sdk::testing_env!(context.block_timestamp(3601 * 1_000_000_000).build());
+
+        assert!(matches!(test_immutables().validate(), Err(EscrowError::OrderExpired)));
+    }
 } 
\ No newline at end of file
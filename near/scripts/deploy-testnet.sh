@@ -148,6 +148,26 @@ if [ $? -ne 0 ]; then
     exit 1
 fi
 
+# Upload approved escrow code to the factory
+echo -e "${GREEN}📦 Uploading escrow code to factory...${NC}"
+near contract call-function as-transaction $FACTORY_ACCOUNT set_escrow_code \
+    file-args target/near/escrow.wasm \
+    prepaid-gas 300.0Tgas \
+    attached-deposit 0 \
+    sign-as $FACTORY_ACCOUNT \
+    network-config $NETWORK \
+    sign-with-keychain \
+    send
+
+if [ $? -ne 0 ]; then
+    echo -e "${RED}❌ Escrow code upload failed${NC}"
+    exit 1
+fi
+
+near contract call-function as-read-only $FACTORY_ACCOUNT get_escrow_code_hash \
+    json-args '{}' \
+    network-config $NETWORK
+
 # Test factory contract
 echo -e "${GREEN}🧪 Testing factory contract...${NC}"
 near contract call-function as-read-only $FACTORY_ACCOUNT get_owner \
@@ -224,4 +244,5 @@ echo -e "${YELLOW}🛠️ Configuration Status:${NC}"
 echo "✅ Factory deployed and initialized"
 echo "✅ Escrow template deployed"  
 echo "✅ Template reference set in factory"
+echo "✅ Approved escrow code uploaded to factory"
 echo "🔄 Ready to create escrows via initialize_escrow() method"
\ No newline at end of file
@@ -79,6 +79,9 @@ echo -e "${GREEN}👤 Creating test escrow accounts...${NC}"
 # Generate hashlock from secret
 HASHLOCK=$(echo -n "$SECRET" | sha256sum | cut -d' ' -f1)
 
+# Orders are valid for an hour; the nonce is only checked against signed orders
+DEADLINE=$(( $(date +%s) + 3600 ))
+
 # Test source escrow creation
 echo -e "${GREEN}🔄 Testing Source Escrow Creation...${NC}"
 
@@ -88,15 +91,22 @@ SRC_IMMUTABLES=$(cat << EOF
   "hashlock": "$HASHLOCK",
   "maker": "alice.testnet",
   "taker": "bob.testnet", 
-  "token": null,
+  "asset": "Near",
   "amount": "1000000000000000000000000",
   "safety_deposit": "100000000000000000000000",
   "timelocks": {
-    "deployed_at": 0,
     "withdrawal_period": 3600,
     "cancellation_period": 7200,
     "rescue_delay": 86400
-  }
+  },
+  "nonce": 0,
+  "src_chain_id": 397,
+  "dst_chain_id": 1,
+  "counterpart_token": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+  "counterpart_amount": "400000000000000000",
+  "evm_maker": null,
+  "evm_receiver": null,
+  "deadline": $DEADLINE
 }
 EOF
 )
@@ -107,9 +117,9 @@ echo "$SRC_IMMUTABLES" | jq '.'
 # For demo, we'll show the call that would be made
 echo -e "${YELLOW}📞 Source escrow creation call:${NC}"
 echo "near contract call-function as-transaction $FACTORY_ACCOUNT create_src_escrow \\"
-echo "  json-args '$SRC_IMMUTABLES' \\"
+echo "  json-args '{\"immutables\": $(echo "$SRC_IMMUTABLES" | jq -c '.')}' \\"
 echo "  prepaid-gas 300.0Tgas \\"
-echo "  attached-deposit 4 \\"  # 4 NEAR for deposit + fees
+echo "  attached-deposit 4.2 \\"  # 3 NEAR storage + 1 NEAR amount + 0.1 NEAR safety deposit + 0.1 NEAR fee
 echo "  network-config $NETWORK \\"
 echo "  sign-with-keychain \\"
 echo "  send"
@@ -126,15 +136,22 @@ DST_IMMUTABLES=$(cat << EOF
   "hashlock": "$HASHLOCK", 
   "maker": "charlie.testnet",
   "taker": "diana.testnet",
-  "token": null,
+  "asset": "Near",
   "amount": "2000000000000000000000000",
   "safety_deposit": "200000000000000000000000", 
   "timelocks": {
-    "deployed_at": 0,
     "withdrawal_period": 3600,
     "cancellation_period": 7200,
     "rescue_delay": 86400
-  }
+  },
+  "nonce": 0,
+  "src_chain_id": 1,
+  "dst_chain_id": 397,
+  "counterpart_token": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+  "counterpart_amount": "400000000000000000",
+  "evm_maker": null,
+  "evm_receiver": null,
+  "deadline": $DEADLINE
 }
 EOF
 )
@@ -144,9 +161,9 @@ echo "$DST_IMMUTABLES" | jq '.'
 
 echo -e "${YELLOW}📞 Destination escrow creation call:${NC}"
 echo "near contract call-function as-transaction $FACTORY_ACCOUNT create_dst_escrow \\"
-echo "  json-args '$DST_IMMUTABLES' \\"
+echo "  json-args '{\"immutables\": $(echo "$DST_IMMUTABLES" | jq -c '.')}' \\"
 echo "  prepaid-gas 300.0Tgas \\"
-echo "  attached-deposit 5.3 \\"  # 5.3 NEAR for deposit + fees
+echo "  attached-deposit 5.3 \\"  # 3 NEAR storage + 2 NEAR amount + 0.2 NEAR safety deposit + 0.1 NEAR fee
 echo "  network-config $NETWORK \\"
 echo "  sign-with-keychain \\"
 echo "  send"
@@ -156,8 +173,9 @@ echo -e "${GREEN}✅ Test commands generated!${NC}"
 echo -e "${YELLOW}💡 To run these tests:${NC}"
 echo "1. Run the source escrow creation command above"
 echo "2. Run the destination escrow creation command above"
-echo "3. Check the created escrows:"
+echo "3. Check the created escrows (the view returns {\"source\": ..., \"destination\": ...}):"
 echo "   near contract call-function as-read-only $FACTORY_ACCOUNT get_escrow_for_order json-args '{\"order_hash\": \"$ORDER_HASH\"}' network-config $NETWORK"
+echo "   near contract call-function as-read-only $FACTORY_ACCOUNT get_escrow_for_order json-args '{\"order_hash\": \"$DST_ORDER_HASH\"}' network-config $NETWORK"
 echo ""
 echo -e "${BLUE}🔍 Remember: The secret for testing is: $SECRET${NC}"
 echo -e "${BLUE}🔍 The hashlock is: $HASHLOCK${NC}"
\ No newline at end of file
@@ -84,6 +84,9 @@ SECRET=$(openssl rand -hex 16)
 HASHLOCK=$(echo -n "$SECRET" | openssl dgst -sha256 -hex | cut -d' ' -f2)
 ORDER_HASH=$(openssl rand -hex 16)
 
+# Orders are valid for an hour; the nonce is only checked against signed orders
+DEADLINE=$(( $(date +%s) + 3600 ))
+
 echo -e "${GREEN}🔐 Generated test data:${NC}"
 echo "Secret: $SECRET"
 echo "Hashlock: $HASHLOCK"
@@ -97,19 +100,30 @@ IMMUTABLES='{
     "hashlock": "'$HASHLOCK'",
     "maker": "'$MAKER_ACCOUNT'",
     "taker": "'$TAKER_ACCOUNT'",
-    "token": null,
+    "asset": "Near",
     "amount": "'$AMOUNT'",
     "safety_deposit": "100000000000000000000000",
     "timelocks": {
-        "deployed_at": 0,
         "withdrawal_period": 3600,
         "cancellation_period": 7200,
         "rescue_delay": 86400
-    }
+    },
+    "nonce": 0,
+    "src_chain_id": 397,
+    "dst_chain_id": 1,
+    "counterpart_token": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+    "counterpart_amount": "400000000000000000",
+    "evm_maker": null,
+    "evm_receiver": null,
+    "deadline": '$DEADLINE'
 }'
 
-# Calculate required deposit (amount + safety_deposit + creation_fee)
-REQUIRED_DEPOSIT="1200000000000000000000000" # 1.2 NEAR
+# Calculate required deposit (creation_fee + 3 NEAR storage + amount + safety_deposit)
+CREATION_FEE=$(near contract call-function as-read-only $FACTORY_ACCOUNT get_creation_fee \
+    json-args '{}' \
+    network-config $NETWORK \
+    now | grep -o '"[0-9]*"' | tr -d '"')
+REQUIRED_DEPOSIT=$(echo "${CREATION_FEE:-0} + 3000000000000000000000000 + $AMOUNT + 100000000000000000000000" | bc)
 
 near contract call-function as-transaction $FACTORY_ACCOUNT create_src_escrow \
     json-args '{"immutables": '$IMMUTABLES'}' \
@@ -125,7 +139,7 @@ echo -e "${GREEN}🔍 Getting escrow address...${NC}"
 ESCROW_ACCOUNT=$(near contract call-function as-read-only $FACTORY_ACCOUNT get_escrow_for_order \
     json-args '{"order_hash": "'$ORDER_HASH'"}' \
     network-config $NETWORK \
-    now | grep -o '"[^"]*"' | tr -d '"')
+    now | grep -o '"source": *"[^"]*"' | cut -d'"' -f4)
 
 if [ -n "$ESCROW_ACCOUNT" ]; then
     echo "Escrow created at: $ESCROW_ACCOUNT"
@@ -150,8 +164,8 @@ if [ -n "$ESCROW_ACCOUNT" ]; then
     
     echo ""
     echo -e "${GREEN}🔐 Checking revealed secret...${NC}"
-    near contract call-function as-read-only $ESCROW_ACCOUNT get_revealed_secret \
-        json-args '{}' \
+    near contract call-function as-read-only $FACTORY_ACCOUNT get_revealed_secret \
+        json-args '{"order_hash": "'$ORDER_HASH'", "fill_index": 0}' \
         network-config $NETWORK \
         now
         